@@ -0,0 +1,8 @@
+DROP TRIGGER IF EXISTS users_touch ON users;
+DROP FUNCTION IF EXISTS users_touch();
+DROP INDEX IF EXISTS users_updated_at_idx;
+
+ALTER TABLE users
+    DROP COLUMN IF EXISTS version,
+    DROP COLUMN IF EXISTS updated_at,
+    DROP COLUMN IF EXISTS created_at;
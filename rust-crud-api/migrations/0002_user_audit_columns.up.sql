@@ -0,0 +1,22 @@
+ALTER TABLE users
+    ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+
+-- Incremental pulls filter and page on (updated_at, id).
+CREATE INDEX users_updated_at_idx ON users (updated_at, id);
+
+-- Every write goes through this, so no query can forget to bump the version
+-- or overwrite created_at.
+CREATE FUNCTION users_touch() RETURNS trigger AS $$
+BEGIN
+    NEW.created_at := OLD.created_at;
+    NEW.updated_at := now();
+    NEW.version := OLD.version + 1;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_touch
+    BEFORE UPDATE ON users
+    FOR EACH ROW EXECUTE FUNCTION users_touch();
@@ -0,0 +1,10 @@
+-- DELETE /users/:id only stamps deleted_at; the purge job removes the row
+-- once the retention period has passed.
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- A deleted user gives up its email straight away so it can be registered
+-- again; restoring it fails if that has happened.
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_email_key;
+CREATE UNIQUE INDEX users_email_key ON users (email) WHERE deleted_at IS NULL;
+
+CREATE INDEX users_deleted_at_idx ON users (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,18 @@
+-- The users table as the Postgres migrations leave it. Timestamps are
+-- RFC 3339 text with microseconds, so comparing them as strings orders them
+-- in time. The repository bumps updated_at and version itself, since
+-- RETURNING does not see changes made by triggers.
+CREATE TABLE users (
+    -- AUTOINCREMENT never reuses the id of a purged user, like SERIAL.
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    version INTEGER NOT NULL DEFAULT 1,
+    deleted_at TEXT
+);
+
+CREATE UNIQUE INDEX users_email_key ON users (email) WHERE deleted_at IS NULL;
+CREATE INDEX users_updated_at_idx ON users (updated_at, id);
+CREATE INDEX users_deleted_at_idx ON users (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,253 @@
+use crate::config::{AdminConfig, ServerConfig};
+use crate::controllers::{HealthController, MetricsController, UserController};
+use crate::http::{Method, ParseError, Request, RequestParser, Response, RouteMatch, Router};
+use crate::logging;
+use crate::metrics;
+use crate::routes::{self, Handler};
+use crate::server::{
+    method_not_allowed, not_found, parse_error_response, redirect, request_timeout, Exchange, CONTINUE, IDLE_POLL_INTERVAL,
+};
+use crate::services::UserService;
+use crate::shutdown::{self, Shutdown};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+enum ReadError {
+    Io(io::Error),
+    Parse(ParseError),
+    TimedOut,
+}
+
+pub struct AsyncServer {
+    listener: TcpListener,
+    handler: Arc<ConnectionHandler>,
+}
+
+// State shared by every connection task.
+struct ConnectionHandler {
+    config: ServerConfig,
+    router: Router<Handler>,
+    shutdown: Shutdown,
+}
+
+impl AsyncServer {
+    pub async fn new(
+        config: ServerConfig,
+        admin: AdminConfig,
+        user_service: Arc<UserService>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind(&config.address).await?;
+        // Readiness fails as soon as a shutdown starts, so load balancers stop
+        // sending new requests while the in-flight ones drain.
+        let shutdown = Shutdown::new();
+        let health_controller = Arc::new(HealthController::new(Arc::clone(&user_service), shutdown.clone()));
+        let metrics_service = Arc::clone(&user_service);
+        let metrics_controller = Arc::new(MetricsController::new(move || metrics_service.pool_state()));
+        let user_controller = Arc::new(UserController::new(user_service, admin));
+        let router = routes::build_router(
+            user_controller,
+            health_controller,
+            metrics_controller,
+            config.trailing_slash,
+            config.docs_page,
+        );
+
+        Ok(AsyncServer {
+            listener,
+            handler: Arc::new(ConnectionHandler {
+                config,
+                router,
+                shutdown,
+            }),
+        })
+    }
+
+    /// Serves connections until SIGTERM or SIGINT, then waits for in-flight
+    /// requests to finish. Fails if connections are still busy when the
+    /// shutdown timeout runs out.
+    pub async fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        logging::info!("server started", address = self.listener.local_addr()?, runtime = "async");
+
+        let mut connections = JoinSet::new();
+        let signal = shutdown::signal_received();
+        tokio::pin!(signal);
+
+        loop {
+            tokio::select! {
+                result = &mut signal => {
+                    result?;
+                    break;
+                }
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, client)) => {
+                        let handler = Arc::clone(&self.handler);
+                        connections.spawn(async move { handler.handle_client(stream, client).await });
+                    }
+                    Err(e) => {
+                        logging::warn!("failed to accept connection", error = e.to_string());
+                    }
+                },
+                // Reap finished connections so the set only holds live ones.
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            }
+        }
+
+        self.handler.shutdown.request();
+        drop(self.listener);
+        logging::info!("stopped accepting connections, draining in-flight requests");
+
+        let drain = async { while connections.join_next().await.is_some() {} };
+        if timeout(self.handler.config.shutdown_timeout, drain).await.is_err() {
+            let busy = connections.len();
+            connections.abort_all();
+            return Err(format!("{} connection(s) still busy after the shutdown timeout", busy).into());
+        }
+        Ok(())
+    }
+}
+
+impl ConnectionHandler {
+    async fn handle_client(&self, mut stream: TcpStream, client: SocketAddr) {
+        let _connection = metrics::connection_opened();
+        let client = Some(client);
+        let mut parser = RequestParser::new(self.config.parser_limits());
+
+        // Requests are answered one at a time in arrival order, which is all
+        // pipelining needs: anything already buffered is parsed before reading.
+        loop {
+            let mut head_only = false;
+            let (exchange, response, keep_alive) = match self.read_request(&mut stream, &mut parser).await {
+                Ok(Some(request)) => {
+                    head_only = request.method == Method::Head;
+                    let mut exchange = Exchange::start(Some(&request));
+                    // Handlers take the request by value, so decide this first.
+                    let wants_keep_alive = request.wants_keep_alive();
+                    let response = self.route_request(request, &mut exchange).await;
+                    // Once shutdown starts, finish this response and hang up.
+                    let keep_alive = self.config.keep_alive_timeout.is_some()
+                        && wants_keep_alive
+                        && !self.shutdown.is_requested();
+                    (exchange, response, keep_alive)
+                }
+                Ok(None) => return,
+                Err(ReadError::Parse(e)) => {
+                    // The framing of anything after a bad request is unknown, so close.
+                    (Exchange::start(None), parse_error_response(&e), false)
+                }
+                Err(ReadError::TimedOut) => {
+                    (Exchange::start(None), request_timeout(), false)
+                }
+                Err(ReadError::Io(e)) => {
+                    logging::debug!("unable to read stream", client = client, error = e.to_string());
+                    return;
+                }
+            };
+
+            // HEAD gets the GET headers, Content-Length included, but no body.
+            let response = response.with_header("X-Request-Id", exchange.request_id.clone());
+            let bytes = response.to_bytes(keep_alive, !head_only);
+            let written = match timeout(self.config.write_timeout, stream.write_all(&bytes)).await {
+                Ok(result) => result.map_err(|e| e.to_string()),
+                Err(_) => Err("timed out".to_string()),
+            };
+            let sent = if written.is_ok() && !head_only { response.body.len() } else { 0 };
+            exchange.finish(&response, sent, client);
+
+            if let Err(e) = written {
+                logging::warn!("failed to write response", client = client, error = e);
+                return;
+            }
+
+            if !keep_alive {
+                return;
+            }
+        }
+    }
+
+    async fn route_request(&self, mut request: Request, exchange: &mut Exchange) -> Response {
+        match self.router.find(request.method, &request.path) {
+            RouteMatch::Found(handler, params, route) => {
+                request.params = params;
+                exchange.route = Some(route.to_string());
+                handler(request).await
+            }
+            RouteMatch::MethodNotAllowed(allowed) => method_not_allowed(&allowed),
+            RouteMatch::Redirect(location) => redirect(&location, &request.query),
+            RouteMatch::NotFound => not_found(&request.path),
+        }
+    }
+
+    async fn read_request(&self, stream: &mut TcpStream, parser: &mut RequestParser) -> Result<Option<Request>, ReadError> {
+        let mut buffer = [0; 4096];
+
+        loop {
+            if let Some(request) = parser.parse().map_err(ReadError::Parse)? {
+                return Ok(Some(request));
+            }
+            if parser.take_continue() {
+                match timeout(self.config.write_timeout, stream.write_all(CONTINUE)).await {
+                    Ok(result) => result.map_err(ReadError::Io)?,
+                    Err(_) => return Err(ReadError::TimedOut),
+                }
+            }
+
+            if parser.is_empty() {
+                if let Some(idle) = self.config.keep_alive_timeout {
+                    if !self.wait_for_request(stream, idle).await? {
+                        return Ok(None);
+                    }
+                }
+            }
+
+            let size = match timeout(self.config.read_timeout, stream.read(&mut buffer)).await {
+                Ok(result) => result.map_err(ReadError::Io)?,
+                Err(_) => return Err(ReadError::TimedOut),
+            };
+            if size == 0 {
+                if parser.is_empty() {
+                    return Ok(None);
+                }
+                return Err(ReadError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed mid-request",
+                )));
+            }
+            parser.feed(&buffer[..size]);
+        }
+    }
+
+    // Waits in short slices for the next request on an idle connection so that
+    // a shutdown does not have to sit out the whole keep-alive timeout.
+    // Returns false if the connection should be closed instead.
+    async fn wait_for_request(&self, stream: &TcpStream, idle: Duration) -> Result<bool, ReadError> {
+        // A timeout too long to add to the clock never runs out.
+        let deadline = Instant::now().checked_add(idle);
+        let mut probe = [0; 1];
+
+        loop {
+            let remaining = deadline.map_or(IDLE_POLL_INTERVAL, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+
+            match timeout(remaining.min(IDLE_POLL_INTERVAL), stream.peek(&mut probe)).await {
+                // Data or end of stream; either way the caller's read handles it.
+                Ok(Ok(_)) => return Ok(true),
+                Ok(Err(e)) => return Err(ReadError::Io(e)),
+                Err(_) => {
+                    if self.shutdown.is_requested() {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use crate::config::settings::Reader;
+use std::time::Duration;
+
+// A century; far past that the cutoff date leaves the range timestamps cover.
+const MAX_RETENTION_DAYS: i32 = 36_500;
+
+#[derive(Clone)]
+pub struct AdminConfig {
+    // Bearer token for the admin-only endpoints; `None` disables them.
+    pub token: Option<String>,
+    // How long soft-deleted users can still be restored before they are purged.
+    pub retention_days: i32,
+    // `None` turns the background purge job off.
+    pub purge_interval: Option<Duration>,
+}
+
+impl AdminConfig {
+    pub fn read(reader: &mut Reader) -> Self {
+        let token = reader.text("admin.token");
+
+        let retention_days = reader.number("admin.retention_days", 30);
+        reader.check(
+            "admin.retention_days",
+            (0..=MAX_RETENTION_DAYS).contains(&retention_days),
+            &format!("must be between 0 and {}", MAX_RETENTION_DAYS),
+        );
+
+        let purge_interval_secs = reader.number("admin.purge_interval_secs", 3600);
+
+        AdminConfig {
+            token,
+            retention_days,
+            purge_interval: (purge_interval_secs > 0).then(|| Duration::from_secs(purge_interval_secs)),
+        }
+    }
+}
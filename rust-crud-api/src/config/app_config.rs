@@ -0,0 +1,48 @@
+use crate::config::settings::{self, CommandLine, Reader, Resolved, Settings};
+use crate::config::{AdminConfig, DatabaseConfig, LoggingConfig, ServerConfig};
+
+/// Everything the binary is configured with. Each layer overrides the one
+/// before it: built-in defaults, the TOML file named by `--config` or
+/// `CONFIG_FILE`, environment variables, then `--section.key value` flags.
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub admin: AdminConfig,
+    pub logging: LoggingConfig,
+    resolved: Vec<Resolved>,
+}
+
+impl AppConfig {
+    /// Loads the configuration from `args` (without the program name) and
+    /// the environment. On failure, returns one message per bad key.
+    pub fn load(args: &[String]) -> Result<(Self, CommandLine), Vec<String>> {
+        let mut errors = Vec::new();
+        let (settings, command_line) = Settings::load(args, &mut errors);
+
+        let mut reader = Reader::new(&settings);
+        let server = ServerConfig::read(&mut reader);
+        let database = DatabaseConfig::read(&mut reader);
+        let admin = AdminConfig::read(&mut reader);
+        let logging = LoggingConfig::read(&mut reader);
+        let (resolved, read_errors) = reader.finish();
+
+        errors.extend(read_errors);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let config = AppConfig {
+            server,
+            database,
+            admin,
+            logging,
+            resolved,
+        };
+        Ok((config, command_line))
+    }
+
+    /// The effective configuration as a config file, with secrets redacted.
+    pub fn to_toml(&self) -> String {
+        settings::render(&self.resolved)
+    }
+}
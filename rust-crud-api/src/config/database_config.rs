@@ -1,16 +1,34 @@
-use std::env;
+use crate::config::settings::Reader;
+use crate::config::{PoolConfig, RetryConfig};
+use crate::database::Backend;
 
 pub struct DatabaseConfig {
+    // A Postgres URL or connection string, or `sqlite://path.db`.
     pub url: String,
+    // Picked by the scheme of `url`.
+    pub backend: Backend,
+    pub pool: PoolConfig,
+    pub retry: RetryConfig,
 }
 
 impl DatabaseConfig {
-    pub fn from_env() -> Result<Self, env::VarError> {
-        let url = env::var("DATABASE_URL")?;
-        Ok(DatabaseConfig { url })
-    }
+    pub fn read(reader: &mut Reader) -> Self {
+        let url = reader.text("database.url").unwrap_or_default();
+        let backend = if url.is_empty() {
+            reader.invalid("database.url", "must be set");
+            Backend::Postgres
+        } else {
+            Backend::from_url(&url).unwrap_or_else(|e| {
+                reader.invalid("database.url", &e);
+                Backend::Postgres
+            })
+        };
 
-    pub fn get_url(&self) -> &str {
-        &self.url
+        DatabaseConfig {
+            url,
+            backend,
+            pool: PoolConfig::read(reader),
+            retry: RetryConfig::read(reader),
+        }
     }
 }
@@ -0,0 +1,55 @@
+use crate::config::settings::Reader;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    // `key=value` pairs, for reading in a terminal.
+    Logfmt,
+    // One JSON object per line, for log collectors.
+    Json,
+}
+
+pub struct LoggingConfig {
+    // Messages less severe than this are dropped.
+    pub level: LogLevel,
+    pub format: LogFormat,
+}
+
+impl LoggingConfig {
+    pub fn read(reader: &mut Reader) -> Self {
+        let level = reader.choice(
+            "logging.level",
+            &[
+                ("error", LogLevel::Error),
+                ("warn", LogLevel::Warn),
+                ("info", LogLevel::Info),
+                ("debug", LogLevel::Debug),
+            ],
+            "info",
+        );
+        let format = reader.choice(
+            "logging.format",
+            &[("logfmt", LogFormat::Logfmt), ("json", LogFormat::Json)],
+            "logfmt",
+        );
+        LoggingConfig { level, format }
+    }
+}
@@ -1,3 +1,16 @@
+pub mod admin_config;
+pub mod app_config;
 pub mod database_config;
+pub mod logging_config;
+pub mod pool_config;
+pub mod retry_config;
+pub mod server_config;
+pub mod settings;
 
+pub use admin_config::AdminConfig;
+pub use app_config::AppConfig;
 pub use database_config::DatabaseConfig;
+pub use logging_config::LoggingConfig;
+pub use pool_config::PoolConfig;
+pub use retry_config::RetryConfig;
+pub use server_config::ServerConfig;
@@ -0,0 +1,38 @@
+use crate::config::settings::Reader;
+use std::time::Duration;
+
+pub struct PoolConfig {
+    pub min_idle: u32,
+    pub max_size: u32,
+    // `None` keeps idle connections open indefinitely.
+    pub idle_timeout: Option<Duration>,
+    pub connection_timeout: Duration,
+}
+
+impl PoolConfig {
+    pub fn read(reader: &mut Reader) -> Self {
+        let min_idle = reader.number("database.pool_min_idle", 2);
+        let max_size = reader.number("database.pool_max_size", 16);
+        reader.check("database.pool_max_size", max_size > 0, "must be at least 1");
+        reader.check(
+            "database.pool_min_idle",
+            min_idle <= max_size,
+            "must not exceed database.pool_max_size",
+        );
+
+        let idle_timeout_secs = reader.number("database.pool_idle_timeout_secs", 300);
+        let connection_timeout_secs = reader.number("database.pool_connection_timeout_secs", 5);
+        reader.check(
+            "database.pool_connection_timeout_secs",
+            connection_timeout_secs > 0,
+            "must be at least 1",
+        );
+
+        PoolConfig {
+            min_idle,
+            max_size,
+            idle_timeout: (idle_timeout_secs > 0).then(|| Duration::from_secs(idle_timeout_secs)),
+            connection_timeout: Duration::from_secs(connection_timeout_secs),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use crate::config::settings::Reader;
+use std::time::Duration;
+
+/// How hard startup tries to reach the database before giving up. The wait
+/// doubles after each failed attempt, up to `max_backoff`.
+pub struct RetryConfig {
+    // 1 gives up on the first failure.
+    pub attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryConfig {
+    pub fn read(reader: &mut Reader) -> Self {
+        let attempts = reader.number("database.connect_attempts", 10);
+        reader.check("database.connect_attempts", attempts > 0, "must be at least 1");
+
+        let initial_backoff_ms: u64 = reader.number("database.connect_backoff_ms", 500);
+        let max_backoff_secs: u64 = reader.number("database.connect_max_backoff_secs", 30);
+        reader.check(
+            "database.connect_max_backoff_secs",
+            max_backoff_secs.saturating_mul(1000) >= initial_backoff_ms,
+            "must not be shorter than database.connect_backoff_ms",
+        );
+
+        RetryConfig {
+            attempts,
+            initial_backoff: Duration::from_millis(initial_backoff_ms),
+            max_backoff: Duration::from_secs(max_backoff_secs),
+        }
+    }
+}
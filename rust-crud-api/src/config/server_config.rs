@@ -0,0 +1,154 @@
+use crate::config::settings::Reader;
+use crate::http::{ParserLimits, TrailingSlash};
+use std::thread;
+use std::time::Duration;
+
+// An hour; anything longer is more likely minutes or milliseconds given in
+// the wrong unit than a real wait.
+const MAX_KEEP_ALIVE_TIMEOUT_SECS: u64 = 3600;
+const MAX_SHUTDOWN_TIMEOUT_SECS: u64 = 3600;
+
+pub struct ServerConfig {
+    // `host:port` to listen on.
+    pub address: String,
+    pub max_header_bytes: usize,
+    pub max_body_bytes: usize,
+    pub worker_threads: usize,
+    // Connections waiting for a worker thread; the async server has no queue.
+    #[cfg(not(feature = "async"))]
+    pub queue_depth: usize,
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+    // `None` turns keep-alive off and closes every connection after one response.
+    pub keep_alive_timeout: Option<Duration>,
+    // How long in-flight requests get to finish after SIGTERM/SIGINT.
+    pub shutdown_timeout: Duration,
+    pub trailing_slash: TrailingSlash,
+    // Serve Swagger UI at `/docs`, in builds with the docs-ui feature;
+    // `/openapi.json` is always served.
+    pub docs_page: bool,
+}
+
+impl ServerConfig {
+    pub fn read(reader: &mut Reader) -> Self {
+        let defaults = ParserLimits::default();
+        let default_workers = thread::available_parallelism()
+            .map(|count| count.get() * 2)
+            .unwrap_or(4);
+
+        let address = reader.text("server.address").unwrap_or_else(|| "0.0.0.0:8080".to_string());
+        let valid_port = address.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        reader.check("server.address", valid_port, "must be host:port");
+
+        let worker_threads = reader.number("server.worker_threads", default_workers);
+        reader.check("server.worker_threads", worker_threads > 0, "must be at least 1");
+
+        let read_timeout_secs = reader.number("server.read_timeout_secs", 30);
+        let write_timeout_secs = reader.number("server.write_timeout_secs", 30);
+        reader.check("server.read_timeout_secs", read_timeout_secs > 0, "must be at least 1");
+        reader.check("server.write_timeout_secs", write_timeout_secs > 0, "must be at least 1");
+
+        let keep_alive_timeout_secs = reader.number("server.keep_alive_timeout_secs", 5);
+        reader.check(
+            "server.keep_alive_timeout_secs",
+            keep_alive_timeout_secs <= MAX_KEEP_ALIVE_TIMEOUT_SECS,
+            &format!("must be between 0 and {}", MAX_KEEP_ALIVE_TIMEOUT_SECS),
+        );
+        let shutdown_timeout_secs = reader.number("server.shutdown_timeout_secs", 30);
+        reader.check(
+            "server.shutdown_timeout_secs",
+            shutdown_timeout_secs <= MAX_SHUTDOWN_TIMEOUT_SECS,
+            &format!("must be between 0 and {}", MAX_SHUTDOWN_TIMEOUT_SECS),
+        );
+
+        let docs_page = reader.flag("server.docs_page", cfg!(feature = "docs-ui"));
+        reader.check(
+            "server.docs_page",
+            !docs_page || cfg!(feature = "docs-ui"),
+            "needs a build with the docs-ui feature",
+        );
+
+        ServerConfig {
+            address,
+            max_header_bytes: reader.number("server.max_header_bytes", defaults.max_header_bytes),
+            max_body_bytes: reader.number("server.max_body_bytes", defaults.max_body_bytes),
+            worker_threads,
+            #[cfg(not(feature = "async"))]
+            queue_depth: reader.number("server.queue_depth", 128),
+            read_timeout: Duration::from_secs(read_timeout_secs),
+            write_timeout: Duration::from_secs(write_timeout_secs),
+            keep_alive_timeout: (keep_alive_timeout_secs > 0).then(|| Duration::from_secs(keep_alive_timeout_secs)),
+            shutdown_timeout: Duration::from_secs(shutdown_timeout_secs),
+            trailing_slash: reader.choice(
+                "server.trailing_slash",
+                &[
+                    ("ignore", TrailingSlash::Ignore),
+                    ("strict", TrailingSlash::Strict),
+                    ("redirect", TrailingSlash::Redirect),
+                ],
+                "ignore",
+            ),
+            docs_page,
+        }
+    }
+
+    pub fn parser_limits(&self) -> ParserLimits {
+        ParserLimits {
+            max_header_bytes: self.max_header_bytes,
+            max_body_bytes: self.max_body_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::settings::Settings;
+
+    fn read(args: &[&str]) -> (ServerConfig, Vec<String>) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut errors = Vec::new();
+        let (settings, _) = Settings::load(&args, &mut errors);
+        let mut reader = Reader::new(&settings);
+        let config = ServerConfig::read(&mut reader);
+        errors.extend(reader.finish().1);
+        (config, errors)
+    }
+
+    #[test]
+    fn timeouts_up_to_the_maximum_are_accepted() {
+        let (config, errors) = read(&["--server.keep_alive_timeout_secs=3600", "--server.shutdown_timeout_secs=0"]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.keep_alive_timeout, Some(Duration::from_secs(3600)));
+        assert_eq!(config.shutdown_timeout, Duration::ZERO);
+    }
+
+    #[test]
+    fn timeouts_past_the_maximum_are_rejected() {
+        let (_, errors) = read(&[
+            "--server.keep_alive_timeout_secs=3601",
+            "--server.shutdown_timeout_secs",
+            "18446744073709551615",
+        ]);
+        assert_eq!(
+            errors,
+            vec![
+                "server.keep_alive_timeout_secs = \"3601\" (flag --server.keep_alive_timeout_secs): \
+                 must be between 0 and 3600",
+                "server.shutdown_timeout_secs = \"18446744073709551615\" (flag --server.shutdown_timeout_secs): \
+                 must be between 0 and 3600",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_docs_page_needs_the_docs_ui_feature() {
+        let (config, errors) = read(&["--server.docs_page=true"]);
+        assert_eq!(errors.is_empty(), cfg!(feature = "docs-ui"), "{:?}", errors);
+        assert!(config.docs_page);
+
+        let (config, errors) = read(&["--server.docs_page=false"]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(!config.docs_page);
+    }
+}
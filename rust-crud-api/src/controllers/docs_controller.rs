@@ -0,0 +1,69 @@
+use crate::http::{Method, Problem, Request, Response, StatusCode};
+use crate::openapi;
+use std::sync::OnceLock;
+
+// Swagger UI pointed at `/openapi.json`. Its assets are swagger-ui 5.17.14,
+// served from here so the page loads nothing from other origins.
+#[cfg(feature = "docs-ui")]
+const DOCS_PAGE: &str = include_str!("../../assets/docs.html");
+#[cfg(feature = "docs-ui")]
+const SWAGGER_UI_CSS: &[u8] = include_bytes!("../../assets/swagger-ui/swagger-ui.css");
+#[cfg(feature = "docs-ui")]
+const SWAGGER_UI_BUNDLE: &[u8] = include_bytes!("../../assets/swagger-ui/swagger-ui-bundle.js");
+// What the bundle's opening comment points readers to.
+#[cfg(feature = "docs-ui")]
+const SWAGGER_UI_LICENSES: &[u8] = include_bytes!("../../assets/swagger-ui/swagger-ui-bundle.js.LICENSE.txt");
+
+/// Serves `GET /openapi.json` and the `/docs` page that renders it.
+pub struct DocsController {
+    // Set once the router is complete, since it describes every route.
+    document: OnceLock<Vec<u8>>,
+}
+
+impl DocsController {
+    pub fn new() -> Self {
+        Self {
+            document: OnceLock::new(),
+        }
+    }
+
+    pub fn publish<'a>(&self, routes: impl Iterator<Item = (Method, &'a str)>) {
+        let document = openapi::document(routes);
+        let _ = self.document.set(serde_json::to_vec(&document).expect("a JSON value always serializes"));
+    }
+
+    pub fn openapi(&self, _request: &Request) -> Response {
+        match self.document.get() {
+            Some(document) => Response::bytes(StatusCode::Ok, "application/json", document.clone()),
+            None => Problem::new(StatusCode::InternalServerError, "internal_error")
+                .detail("The API description has not been built")
+                .into_response(),
+        }
+    }
+
+    #[cfg(feature = "docs-ui")]
+    pub fn docs(&self, _request: &Request) -> Response {
+        Response::bytes(StatusCode::Ok, "text/html; charset=utf-8", DOCS_PAGE.as_bytes().to_vec())
+    }
+
+    #[cfg(feature = "docs-ui")]
+    pub fn swagger_ui_css(&self, _request: &Request) -> Response {
+        asset("text/css; charset=utf-8", SWAGGER_UI_CSS)
+    }
+
+    #[cfg(feature = "docs-ui")]
+    pub fn swagger_ui_bundle(&self, _request: &Request) -> Response {
+        asset("text/javascript; charset=utf-8", SWAGGER_UI_BUNDLE)
+    }
+
+    #[cfg(feature = "docs-ui")]
+    pub fn swagger_ui_licenses(&self, _request: &Request) -> Response {
+        asset("text/plain; charset=utf-8", SWAGGER_UI_LICENSES)
+    }
+}
+
+// The assets only change with the binary, so browsers may keep them a while.
+#[cfg(feature = "docs-ui")]
+fn asset(content_type: &str, body: &[u8]) -> Response {
+    Response::bytes(StatusCode::Ok, content_type, body.to_vec()).with_header("Cache-Control", "public, max-age=86400")
+}
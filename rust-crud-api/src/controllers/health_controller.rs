@@ -0,0 +1,241 @@
+use crate::database::DatabaseError;
+use crate::http::{Request, Response, StatusCode};
+use crate::services::UserService;
+use crate::shutdown::Shutdown;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize)]
+struct Check {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending: Option<usize>,
+}
+
+impl Check {
+    fn status(status: &'static str) -> Self {
+        Check {
+            status,
+            latency_ms: None,
+            pending: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Checks {
+    database: Check,
+    migrations: Check,
+    shutdown: Check,
+}
+
+#[derive(Serialize)]
+struct ReadinessBody {
+    status: &'static str,
+    checks: Checks,
+}
+
+/// What a readiness probe found.
+struct Readiness {
+    // How long the ping took.
+    pub database: Result<Duration, DatabaseError>,
+    // None when the database could not be reached to ask.
+    pub migrations: Option<Result<usize, DatabaseError>>,
+    pub shutting_down: bool,
+}
+
+impl Readiness {
+    // 200 when every check passed, 503 otherwise. Driver errors are logged
+    // with the request rather than shown to whoever is probing.
+    fn into_response(self) -> Response {
+        let mut ready = !self.shutting_down;
+        let mut cause = None;
+
+        let database = match self.database {
+            Ok(latency) => Check {
+                latency_ms: Some((latency.as_secs_f64() * 1_000_000.0).round() / 1000.0),
+                ..Check::status("ok")
+            },
+            Err(e) => {
+                ready = false;
+                cause = Some(e);
+                Check::status("unreachable")
+            }
+        };
+        let migrations = match self.migrations {
+            Some(Ok(0)) => Check::status("ok"),
+            Some(Ok(pending)) => {
+                ready = false;
+                Check {
+                    pending: Some(pending),
+                    ..Check::status("pending")
+                }
+            }
+            Some(Err(e)) => {
+                ready = false;
+                cause = cause.or(Some(e));
+                Check::status("error")
+            }
+            None => Check::status("unknown"),
+        };
+        let shutdown = Check::status(if self.shutting_down { "shutting_down" } else { "ok" });
+
+        let body = ReadinessBody {
+            status: if ready { "ready" } else { "not_ready" },
+            checks: Checks {
+                database,
+                migrations,
+                shutdown,
+            },
+        };
+        let status = if ready { StatusCode::Ok } else { StatusCode::ServiceUnavailable };
+        let response = Response::json(status, &body).with_header("Cache-Control", "no-store");
+        match cause {
+            Some(e) => response.with_cause(e),
+            None => response,
+        }
+    }
+}
+
+/// Serves the liveness and readiness probes.
+pub struct HealthController {
+    user_service: Arc<UserService>,
+    shutdown: Shutdown,
+}
+
+impl HealthController {
+    pub fn new(user_service: Arc<UserService>, shutdown: Shutdown) -> Self {
+        Self { user_service, shutdown }
+    }
+
+    /// `GET /healthz`: answers as long as the process can serve requests at all.
+    pub fn healthz(&self, _request: &Request) -> Response {
+        Response::json(StatusCode::Ok, &Check::status("ok")).with_header("Cache-Control", "no-store")
+    }
+
+    /// `GET /readyz`: whether the database answers, its schema is current
+    /// and the server is not shutting down.
+    pub async fn readyz(&self, _request: &Request) -> Response {
+        let started = Instant::now();
+        let database = self.user_service.ping().await.map(|()| started.elapsed());
+        let migrations = match database {
+            Ok(_) => Some(self.user_service.pending_migrations().await),
+            Err(_) => None,
+        };
+
+        Readiness {
+            database,
+            migrations,
+            shutting_down: self.shutdown.is_requested(),
+        }
+        .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::Version;
+    use crate::http::{Headers, Method, PathParams, Query};
+    use crate::repositories::InMemoryUserRepository;
+    use crate::utils::block_on;
+    use serde_json::{json, Value};
+
+    fn controller(repository: InMemoryUserRepository, shutdown: Shutdown) -> HealthController {
+        HealthController::new(Arc::new(UserService::new(Box::new(repository))), shutdown)
+    }
+
+    fn request(path: &str) -> Request {
+        Request {
+            method: Method::Get,
+            path: path.to_string(),
+            query: Query::default(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: PathParams::default(),
+        }
+    }
+
+    fn readyz(repository: InMemoryUserRepository, shutdown: Shutdown) -> (StatusCode, Value, Response) {
+        let response = block_on(controller(repository, shutdown).readyz(&request("/readyz")));
+        let body = serde_json::from_slice(&response.body).unwrap();
+        (response.status, body, response)
+    }
+
+    #[test]
+    fn healthz_is_ok_even_while_shutting_down() {
+        let shutdown = Shutdown::new();
+        shutdown.request();
+        let response = controller(InMemoryUserRepository::new().unreachable(), shutdown).healthz(&request("/healthz"));
+
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.headers.get("Cache-Control"), Some("no-store"));
+        assert_eq!(serde_json::from_slice::<Value>(&response.body).unwrap(), json!({ "status": "ok" }));
+    }
+
+    #[test]
+    fn readyz_is_ready_when_every_check_passes() {
+        let (status, mut body, response) = readyz(InMemoryUserRepository::new(), Shutdown::new());
+
+        assert_eq!(status, StatusCode::Ok);
+        assert_eq!(response.headers.get("Cache-Control"), Some("no-store"));
+        assert!(body["checks"]["database"]["latency_ms"].as_f64().is_some_and(|latency| latency >= 0.0));
+        body["checks"]["database"].as_object_mut().unwrap().remove("latency_ms");
+        assert_eq!(
+            body,
+            json!({
+                "status": "ready",
+                "checks": {
+                    "database": { "status": "ok" },
+                    "migrations": { "status": "ok" },
+                    "shutdown": { "status": "ok" },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn readyz_is_unavailable_once_shutdown_is_requested() {
+        let shutdown = Shutdown::new();
+        shutdown.request();
+        let (status, body, _) = readyz(InMemoryUserRepository::new(), shutdown);
+
+        assert_eq!(status, StatusCode::ServiceUnavailable);
+        assert_eq!(body["status"], "not_ready");
+        assert_eq!(body["checks"]["database"]["status"], "ok");
+        assert_eq!(body["checks"]["shutdown"]["status"], "shutting_down");
+    }
+
+    #[test]
+    fn readyz_is_unavailable_when_the_database_ping_fails() {
+        let (status, body, response) = readyz(InMemoryUserRepository::new().unreachable(), Shutdown::new());
+
+        assert_eq!(status, StatusCode::ServiceUnavailable);
+        assert_eq!(
+            body,
+            json!({
+                "status": "not_ready",
+                "checks": {
+                    "database": { "status": "unreachable" },
+                    "migrations": { "status": "unknown" },
+                    "shutdown": { "status": "ok" },
+                },
+            })
+        );
+        // Logged with the request, not shown to the prober.
+        assert!(response.cause.is_some());
+        assert!(!String::from_utf8_lossy(&response.body).contains("refused"));
+    }
+
+    #[test]
+    fn readyz_is_unavailable_while_migrations_are_pending() {
+        let (status, body, _) = readyz(InMemoryUserRepository::new().with_pending_migrations(2), Shutdown::new());
+
+        assert_eq!(status, StatusCode::ServiceUnavailable);
+        assert_eq!(body["checks"]["migrations"], json!({ "status": "pending", "pending": 2 }));
+    }
+}
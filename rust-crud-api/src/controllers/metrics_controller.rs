@@ -0,0 +1,24 @@
+use crate::database::PoolState;
+use crate::http::{Request, Response, StatusCode};
+use crate::metrics;
+
+type PoolStateFn = Box<dyn Fn() -> Option<PoolState> + Send + Sync>;
+
+/// Serves `GET /metrics` for Prometheus to scrape.
+pub struct MetricsController {
+    // Sampled on each scrape, so the pool gauges are current.
+    pool_state: PoolStateFn,
+}
+
+impl MetricsController {
+    pub fn new(pool_state: impl Fn() -> Option<PoolState> + Send + Sync + 'static) -> Self {
+        Self {
+            pool_state: Box::new(pool_state),
+        }
+    }
+
+    pub fn metrics(&self, _request: &Request) -> Response {
+        let body = metrics::render((self.pool_state)());
+        Response::bytes(StatusCode::Ok, metrics::CONTENT_TYPE, body.into_bytes())
+    }
+}
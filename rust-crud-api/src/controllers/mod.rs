@@ -1,3 +1,9 @@
+pub mod docs_controller;
+pub mod health_controller;
+pub mod metrics_controller;
 pub mod user_controller;
 
+pub use docs_controller::DocsController;
+pub use health_controller::HealthController;
+pub use metrics_controller::MetricsController;
 pub use user_controller::UserController;
@@ -1,116 +1,473 @@
+use crate::config::AdminConfig;
+use crate::http::query::encode_pairs;
+use crate::http::router::PathParamError;
+use crate::http::{EntityTags, Problem, Request, Response, StatusCode};
+use crate::models::user_query::include_deleted;
+use crate::models::{User, UserPage, UserQuery};
+use crate::services::user_service::ServiceError;
 use crate::services::UserService;
-use crate::utils::{get_id_from_request, get_user_from_request_body};
-use std::sync::{Arc, Mutex};
+use crate::utils::{get_patch_from_request_body, get_user_from_request_body, PatchBodyError, JSON_PATCH, MERGE_PATCH};
+use crate::validation::FieldError;
+use serde::Serialize;
+use std::sync::Arc;
 
-pub const OK_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n";
-pub const NOT_FOUND: &str = "HTTP/1.1 404 NOT FOUND\r\n\r\n";
-pub const INTERNAL_ERROR: &str = "HTTP/1.1 500 INTERNAL SERVER ERROR\r\n\r\n";
-pub const BAD_REQUEST: &str = "HTTP/1.1 400 BAD REQUEST\r\n\r\n";
+// Envelope for `GET /users`; the links are null on the first and last page.
+#[derive(Serialize)]
+struct PageBody<'a> {
+    items: &'a [User],
+    total: i64,
+    limit: i64,
+    next: Option<String>,
+    prev: Option<String>,
+}
+
+// Body of `POST /users/purge`.
+#[derive(Serialize)]
+struct PurgeBody {
+    purged: u64,
+}
 
 pub struct UserController {
-    user_service: Arc<Mutex<UserService>>,
+    user_service: Arc<UserService>,
+    admin: AdminConfig,
 }
 
 impl UserController {
-    pub fn new(user_service: Arc<Mutex<UserService>>) -> Self {
-        Self { user_service }
+    pub fn new(user_service: Arc<UserService>, admin: AdminConfig) -> Self {
+        Self { user_service, admin }
     }
 
-    pub fn create_user(&self, request: &str) -> (String, String) {
+    pub async fn create_user(&self, request: &Request) -> Response {
         match get_user_from_request_body(request) {
             Ok(user) => {
-                let mut service = match self.user_service.lock() {
-                    Ok(service) => service,
-                    Err(_) => return (INTERNAL_ERROR.to_string(), "Service lock error".to_string()),
-                };
-
-                match service.create_user(&user) {
-                    Ok(_) => (OK_RESPONSE.to_string(), "User Created".to_string()),
-                    Err(_) => (INTERNAL_ERROR.to_string(), "Failed to create user".to_string()),
+                match self.user_service.create_user(&user).await {
+                    Ok(user) => created(&user),
+                    Err(e) => service_error(e),
                 }
             }
-            Err(_) => (BAD_REQUEST.to_string(), "Invalid JSON body".to_string()),
+            Err(e) => invalid_body(e),
         }
     }
 
-    pub fn get_user(&self, request: &str) -> (String, String) {
-        match get_id_from_request(request).parse::<i32>() {
+    pub async fn get_user(&self, request: &Request) -> Response {
+        let include_deleted = match include_deleted(&request.query) {
+            Ok(include_deleted) => include_deleted,
+            Err(error) => return invalid_query(vec![error]),
+        };
+        if include_deleted {
+            if let Err(response) = require_admin(request, &self.admin) {
+                return response;
+            }
+        }
+
+        match request.params.parse::<i32>("id") {
             Ok(id) => {
-                let mut service = match self.user_service.lock() {
-                    Ok(service) => service,
-                    Err(_) => return (INTERNAL_ERROR.to_string(), "Service lock error".to_string()),
-                };
-
-                match service.get_user_by_id(id) {
-                    Ok(Some(user)) => {
-                        match serde_json::to_string(&user) {
-                            Ok(json) => (OK_RESPONSE.to_string(), json),
-                            Err(_) => (INTERNAL_ERROR.to_string(), "JSON serialization error".to_string()),
-                        }
-                    }
-                    Ok(None) => (NOT_FOUND.to_string(), "User not found".to_string()),
-                    Err(_) => (INTERNAL_ERROR.to_string(), "Service error".to_string()),
+                match self.user_service.get_user_by_id(id, include_deleted).await {
+                    Ok(Some(user)) => current_user(request, &user),
+                    Ok(None) => user_not_found(id),
+                    Err(e) => service_error(e),
                 }
             }
-            Err(_) => (BAD_REQUEST.to_string(), "Invalid user ID".to_string()),
+            Err(e) => invalid_user_id(e),
         }
     }
 
-    pub fn get_all_users(&self, _request: &str) -> (String, String) {
-        let mut service = match self.user_service.lock() {
-            Ok(service) => service,
-            Err(_) => return (INTERNAL_ERROR.to_string(), "Service lock error".to_string()),
+    pub async fn list_users(&self, request: &Request) -> Response {
+        let query = match UserQuery::from_query(&request.query) {
+            Ok(query) => query,
+            Err(errors) => return invalid_query(errors),
         };
-
-        match service.get_all_users() {
-            Ok(users) => {
-                match serde_json::to_string(&users) {
-                    Ok(json) => (OK_RESPONSE.to_string(), json),
-                    Err(_) => (INTERNAL_ERROR.to_string(), "JSON serialization error".to_string()),
-                }
+        if query.include_deleted {
+            if let Err(response) = require_admin(request, &self.admin) {
+                return response;
             }
-            Err(_) => (INTERNAL_ERROR.to_string(), "Service error".to_string()),
+        }
+
+        match self.user_service.list_users(&query).await {
+            Ok(page) => page_response(request, &query, &page),
+            Err(e) => service_error(e),
         }
     }
 
-    pub fn update_user(&self, request: &str) -> (String, String) {
-        let id = match get_id_from_request(request).parse::<i32>() {
+    pub async fn update_user(&self, request: &Request) -> Response {
+        let id = match request.params.parse::<i32>("id") {
             Ok(id) => id,
-            Err(_) => return (BAD_REQUEST.to_string(), "Invalid user ID".to_string()),
+            Err(e) => return invalid_user_id(e),
         };
 
         match get_user_from_request_body(request) {
             Ok(user) => {
-                let mut service = match self.user_service.lock() {
-                    Ok(service) => service,
-                    Err(_) => return (INTERNAL_ERROR.to_string(), "Service lock error".to_string()),
-                };
-
-                match service.update_user(id, &user) {
-                    Ok(true) => (OK_RESPONSE.to_string(), "User updated".to_string()),
-                    Ok(false) => (NOT_FOUND.to_string(), "User not found".to_string()),
-                    Err(_) => (INTERNAL_ERROR.to_string(), "Failed to update user".to_string()),
+                match self.user_service.update_user(id, &user, if_match(request).as_deref()).await {
+                    Ok(Some(user)) => user_response(StatusCode::Ok, &user),
+                    Ok(None) => user_not_found(id),
+                    Err(e) => service_error(e),
+                }
+            }
+            Err(e) => invalid_body(e),
+        }
+    }
+
+    pub async fn patch_user(&self, request: &Request) -> Response {
+        let id = match request.params.parse::<i32>("id") {
+            Ok(id) => id,
+            Err(e) => return invalid_user_id(e),
+        };
+
+        let patch = match get_patch_from_request_body(request) {
+            Ok(patch) => patch,
+            Err(e) => return invalid_patch_body(e),
+        };
+
+        match self.user_service.patch_user(id, &patch, if_match(request).as_deref()).await {
+            Ok(Some(user)) => user_response(StatusCode::Ok, &user),
+            Ok(None) => user_not_found(id),
+            Err(e) => service_error(e),
+        }
+    }
+
+    pub async fn delete_user(&self, request: &Request) -> Response {
+        match request.params.parse::<i32>("id") {
+            Ok(id) => {
+                match self.user_service.delete_user(id, if_match(request).as_deref()).await {
+                    Ok(true) => Response::text(StatusCode::Ok, "User deleted"),
+                    Ok(false) => user_not_found(id),
+                    Err(e) => service_error(e),
                 }
             }
-            Err(_) => (BAD_REQUEST.to_string(), "Invalid JSON body".to_string()),
+            Err(e) => invalid_user_id(e),
         }
     }
 
-    pub fn delete_user(&self, request: &str) -> (String, String) {
-        match get_id_from_request(request).parse::<i32>() {
+    pub async fn restore_user(&self, request: &Request) -> Response {
+        if let Err(response) = require_admin(request, &self.admin) {
+            return response;
+        }
+
+        match request.params.parse::<i32>("id") {
             Ok(id) => {
-                let mut service = match self.user_service.lock() {
-                    Ok(service) => service,
-                    Err(_) => return (INTERNAL_ERROR.to_string(), "Service lock error".to_string()),
-                };
-
-                match service.delete_user(id) {
-                    Ok(true) => (OK_RESPONSE.to_string(), "User deleted".to_string()),
-                    Ok(false) => (NOT_FOUND.to_string(), "User not found".to_string()),
-                    Err(_) => (INTERNAL_ERROR.to_string(), "Failed to delete user".to_string()),
+                match self.user_service.restore_user(id).await {
+                    Ok(Some(user)) => user_response(StatusCode::Ok, &user),
+                    Ok(None) => user_not_found(id),
+                    Err(e) => service_error(e),
                 }
             }
-            Err(_) => (BAD_REQUEST.to_string(), "Invalid user ID".to_string()),
+            Err(e) => invalid_user_id(e),
+        }
+    }
+
+    // Runs the retention purge now rather than waiting for the background job.
+    pub async fn purge_users(&self, request: &Request) -> Response {
+        if let Err(response) = require_admin(request, &self.admin) {
+            return response;
+        }
+
+        match self.user_service.purge_deleted(self.admin.retention_days).await {
+            Ok(purged) => Response::json(StatusCode::Ok, &PurgeBody { purged }),
+            Err(e) => service_error(e),
+        }
+    }
+}
+
+// Admin endpoints take `Authorization: Bearer <ADMIN_TOKEN>`; without a
+// configured token nobody is an admin.
+fn require_admin(request: &Request, admin: &AdminConfig) -> Result<(), Response> {
+    let token = request
+        .headers
+        .get("Authorization")
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+        .map(|(_, token)| token.trim());
+
+    match (token, admin.token.as_deref()) {
+        (Some(given), Some(expected)) if constant_time_eq(given.as_bytes(), expected.as_bytes()) => Ok(()),
+        (Some(_), _) => Err(Problem::new(StatusCode::Forbidden, "forbidden")
+            .detail("The admin token is not valid")
+            .into_response()),
+        (None, _) => Err(Problem::new(StatusCode::Unauthorized, "unauthorized")
+            .detail("This needs an admin token")
+            .into_response()
+            .with_header("WWW-Authenticate", "Bearer")),
+    }
+}
+
+// Does not stop at the first differing byte, so timing reveals nothing about
+// how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// Strong, since a version always serialises to the same bytes.
+fn etag(user: &User) -> String {
+    format!("\"{}\"", user.version.unwrap_or_default())
+}
+
+// Versions the client's `If-Match` accepts; None without one, or for `*`,
+// which any existing user satisfies. Weak tags never match (RFC 9110 13.1.1).
+fn if_match(request: &Request) -> Option<Vec<i64>> {
+    match request.entity_tags("If-Match")? {
+        EntityTags::Any => None,
+        EntityTags::Tags(tags) => Some(
+            tags.iter()
+                .filter(|tag| !tag.weak)
+                .filter_map(|tag| tag.opaque.parse().ok())
+                .collect(),
+        ),
+    }
+}
+
+fn user_response(status: StatusCode, user: &User) -> Response {
+    Response::json(status, user).with_header("ETag", etag(user))
+}
+
+// 304 if the client's `If-None-Match` already names this version.
+fn current_user(request: &Request, user: &User) -> Response {
+    let tag = etag(user);
+    match request.entity_tags("If-None-Match") {
+        Some(tags) if tags.matches_weak(tag.trim_matches('"')) => {
+            Response::new(StatusCode::NotModified).with_header("ETag", tag)
+        }
+        _ => user_response(StatusCode::Ok, user),
+    }
+}
+
+fn created(user: &User) -> Response {
+    let response = user_response(StatusCode::Created, user);
+    match user.id {
+        Some(id) => response.with_header("Location", format!("/users/{}", id)),
+        None => response,
+    }
+}
+
+fn service_error(error: ServiceError) -> Response {
+    match error {
+        ServiceError::ValidationError(errors) => Problem::new(StatusCode::UnprocessableEntity, "validation_failed")
+            .detail("One or more fields are invalid")
+            .errors(errors)
+            .into_response(),
+        ServiceError::Conflict(errors) => Problem::new(StatusCode::Conflict, "conflict")
+            .detail("The request conflicts with existing data")
+            .errors(errors)
+            .into_response(),
+        ServiceError::PatchFailed(message) => Problem::new(StatusCode::Conflict, "patch_failed")
+            .detail(message)
+            .into_response(),
+        ServiceError::PreconditionFailed => Problem::new(StatusCode::PreconditionFailed, "precondition_failed")
+            .detail("The user has changed since the version given in If-Match")
+            .into_response(),
+        ServiceError::Retryable => Problem::new(StatusCode::ServiceUnavailable, "transaction_conflict")
+            .detail("A concurrent update got in the way, try again")
+            .into_response()
+            .with_header("Retry-After", "1"),
+        // The cause goes to the log; clients only learn that it failed.
+        ServiceError::DatabaseError(e) => Problem::new(StatusCode::InternalServerError, "internal_error")
+            .detail("The request could not be completed")
+            .into_response()
+            .with_cause(e),
+    }
+}
+
+fn invalid_body(error: serde_json::Error) -> Response {
+    Problem::new(StatusCode::BadRequest, "invalid_json")
+        .detail(format!("Invalid JSON body: {}", error))
+        .into_response()
+}
+
+fn invalid_patch_body(error: PatchBodyError) -> Response {
+    match error {
+        PatchBodyError::Json(e) => invalid_body(e),
+        PatchBodyError::UnsupportedMediaType => Problem::new(StatusCode::UnsupportedMediaType, "unsupported_media_type")
+            .detail(format!("PATCH bodies must be {} or {}", MERGE_PATCH, JSON_PATCH))
+            .into_response()
+            .with_header("Accept-Patch", format!("{}, {}", MERGE_PATCH, JSON_PATCH)),
+    }
+}
+
+fn invalid_query(errors: Vec<FieldError>) -> Response {
+    Problem::new(StatusCode::BadRequest, "invalid_query")
+        .detail("One or more query parameters are invalid")
+        .errors(errors)
+        .into_response()
+}
+
+fn page_response(request: &Request, query: &UserQuery, page: &UserPage) -> Response {
+    let link = |param: &'static str, value: i64| {
+        let mut params = query.link_params();
+        params.push((param, value.to_string()));
+        format!("{}?{}", request.path, encode_pairs(params))
+    };
+
+    // Keyset pages only link forward; offset pages link both ways.
+    let (next, prev) = match query.after {
+        Some(_) => {
+            let last = page.users.last().and_then(|user| user.id);
+            (last.filter(|_| page.has_more).map(|id| link("after", id.into())), None)
+        }
+        None => (
+            page.has_more.then(|| link("offset", query.offset + query.limit)),
+            (query.offset > 0).then(|| link("offset", (query.offset - query.limit).max(0))),
+        ),
+    };
+
+    Response::json(
+        StatusCode::Ok,
+        &PageBody {
+            items: &page.users,
+            total: page.total,
+            limit: query.limit,
+            next,
+            prev,
+        },
+    )
+}
+
+fn invalid_user_id(error: PathParamError) -> Response {
+    Problem::new(StatusCode::BadRequest, "invalid_user_id")
+        .detail(format!("Invalid user ID: {}", error))
+        .into_response()
+}
+
+fn user_not_found(id: i32) -> Response {
+    Problem::new(StatusCode::NotFound, "user_not_found")
+        .detail(format!("User {} not found", id))
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::Version;
+    use crate::http::{Headers, Method, PathParams, Query};
+    use serde_json::{json, Value};
+
+    fn request(path: &str, query: &str, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request {
+            method: Method::Get,
+            path: path.to_string(),
+            query: Query::parse(query),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: PathParams::default(),
+        };
+        for (name, value) in headers {
+            request.headers.append(name.to_string(), value.to_string());
         }
+        request
+    }
+
+    fn user(id: i32, version: i64) -> User {
+        let mut user: User = serde_json::from_value(json!({ "id": id, "name": "Ada", "email": "ada@example.com" }))
+            .unwrap();
+        user.version = Some(version);
+        user
+    }
+
+    // The `next` and `prev` links of the page `query` asks for, given the
+    // ids the repository returned and whether more rows follow.
+    fn links(query: &str, ids: &[i32], has_more: bool) -> (Value, Value) {
+        let request = request("/users", query, &[]);
+        let page = UserPage {
+            users: ids.iter().map(|&id| user(id, 1)).collect(),
+            total: 50,
+            has_more,
+        };
+        let response = page_response(&request, &UserQuery::from_query(&request.query).unwrap(), &page);
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        (body["next"].clone(), body["prev"].clone())
+    }
+
+    #[test]
+    fn offset_pages_link_both_ways() {
+        let cases = [
+            ("limit=10", true, json!("/users?limit=10&sort=id&offset=10"), Value::Null),
+            (
+                "limit=10&offset=10",
+                true,
+                json!("/users?limit=10&sort=id&offset=20"),
+                json!("/users?limit=10&sort=id&offset=0"),
+            ),
+            ("limit=10&offset=40", false, Value::Null, json!("/users?limit=10&sort=id&offset=30")),
+            // A page that starts mid-way links back to the very first row.
+            ("limit=10&offset=4", false, Value::Null, json!("/users?limit=10&sort=id&offset=0")),
+            ("limit=10", false, Value::Null, Value::Null),
+        ];
+
+        for (query, has_more, next, prev) in cases {
+            assert_eq!(links(query, &[1, 2], has_more), (next, prev), "{}", query);
+        }
+    }
+
+    #[test]
+    fn keyset_pages_link_forward_from_the_last_row() {
+        assert_eq!(
+            links("after=3&limit=2&sort=-name", &[9, 4], true),
+            (json!("/users?limit=2&sort=-name,-id&after=4"), Value::Null)
+        );
+        assert_eq!(links("after=3&limit=2", &[4, 5], false), (Value::Null, Value::Null));
+        assert_eq!(links("after=3", &[], true), (Value::Null, Value::Null));
+    }
+
+    #[test]
+    fn links_keep_the_filters() {
+        let (next, _) = links("email_domain=ex%20ample.com&name_contains=a%26b&include_deleted=true", &[1], true);
+        assert_eq!(
+            next,
+            "/users?limit=20&sort=id&email_domain=ex%20ample.com&name_contains=a%26b&include_deleted=true&offset=20"
+        );
+    }
+
+    #[test]
+    fn if_match_keeps_only_strong_version_tags() {
+        let cases: [(&[&str], Option<Vec<i64>>); 9] = [
+            (&[], None),
+            (&["*"], None),
+            (&["\"3\""], Some(vec![3])),
+            (&["\"1\", \"2\""], Some(vec![1, 2])),
+            (&["\"1\"", "\"2\""], Some(vec![1, 2])),
+            // Anything that cannot name a version leaves nothing to match,
+            // which the service answers with 412.
+            (&["W/\"3\""], Some(vec![])),
+            (&["W/\"3\", \"4\""], Some(vec![4])),
+            (&["\"abc\""], Some(vec![])),
+            (&["3"], Some(vec![])),
+        ];
+
+        for (values, expected) in cases {
+            let headers: Vec<(&str, &str)> = values.iter().map(|value| ("If-Match", *value)).collect();
+            assert_eq!(if_match(&request("/users/1", "", &headers)), expected, "{:?}", values);
+        }
+    }
+
+    #[test]
+    fn if_none_match_answers_304_for_the_current_version() {
+        let cases = [
+            (None, StatusCode::Ok),
+            (Some("\"5\""), StatusCode::NotModified),
+            (Some("W/\"5\""), StatusCode::NotModified),
+            (Some("*"), StatusCode::NotModified),
+            (Some("\"1\", W/\"5\""), StatusCode::NotModified),
+            (Some("\"4\""), StatusCode::Ok),
+            (Some("\"1\", \"55\""), StatusCode::Ok),
+            (Some("5"), StatusCode::Ok),
+            (Some("\"5"), StatusCode::Ok),
+        ];
+
+        for (value, status) in cases {
+            let headers: Vec<(&str, &str)> = value.iter().map(|value| ("If-None-Match", *value)).collect();
+            let response = current_user(&request("/users/1", "", &headers), &user(1, 5));
+            assert_eq!(response.status, status, "{:?}", value);
+            assert_eq!(response.headers.get("ETag"), Some("\"5\""));
+            assert_eq!(response.body.is_empty(), status == StatusCode::NotModified, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn other_patch_media_types_are_a_415_that_lists_the_accepted_ones() {
+        let response = invalid_patch_body(PatchBodyError::UnsupportedMediaType);
+        assert_eq!(response.status, StatusCode::UnsupportedMediaType);
+        assert_eq!(
+            response.headers.get("Accept-Patch"),
+            Some("application/merge-patch+json, application/json-patch+json")
+        );
     }
 }
@@ -1,34 +1,485 @@
-use postgres::{Client, NoTls, Error as PostgresError};
-use std::env;
+use crate::config::{DatabaseConfig, RetryConfig};
+use crate::logging;
+use postgres::error::SqlState;
+use postgres::{NoTls, Error as PostgresError};
+#[cfg(not(feature = "async"))]
+use r2d2_postgres::PostgresConnectionManager;
+use std::error::Error as _;
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::Duration;
 
+#[cfg(not(feature = "async"))]
+pub type PooledClient = r2d2::PooledConnection<PostgresConnectionManager<NoTls>>;
+
+// The driver error behind a `DatabaseError`, whichever store raised it.
+type ErrorSource = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    // No healthy connection could be checked out in time.
+    Pool(ErrorSource),
+    // The statement broke a table constraint, usually because of the input.
+    // Stores that enforce the constraints themselves have no driver error.
+    Constraint(ConstraintViolation, Option<ErrorSource>),
+    // Lost a race with a concurrent transaction (40001/40P01, or a busy
+    // SQLite database); safe to retry.
+    SerializationFailure(ErrorSource),
+    Postgres(PostgresError),
+    #[cfg(all(feature = "sqlite", not(feature = "async")))]
+    Sqlite(rusqlite::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstraintViolation {
+    // `columns` comes from the error detail, e.g. `Key (email)=(...)`.
+    Unique { constraint: Option<String>, columns: Vec<String> },
+    ForeignKey { constraint: Option<String> },
+    Check { constraint: Option<String> },
+    NotNull { column: Option<String> },
+}
+
+impl fmt::Display for ConstraintViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, name) = match self {
+            ConstraintViolation::Unique { constraint, .. } => ("unique", constraint),
+            ConstraintViolation::ForeignKey { constraint } => ("foreign key", constraint),
+            ConstraintViolation::Check { constraint } => ("check", constraint),
+            ConstraintViolation::NotNull { column } => ("not-null", column),
+        };
+        match name {
+            Some(name) => write!(f, "{} violation on {}", kind, name),
+            None => write!(f, "{} violation", kind),
+        }
+    }
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Pool(e) => write!(f, "connection pool error: {}", e),
+            DatabaseError::Constraint(violation, _) => write!(f, "constraint error: {}", violation),
+            DatabaseError::SerializationFailure(e) => write!(f, "serialization failure: {}", e),
+            DatabaseError::Postgres(e) => write!(f, "postgres error: {}", e),
+            #[cfg(all(feature = "sqlite", not(feature = "async")))]
+            DatabaseError::Sqlite(e) => write!(f, "sqlite error: {}", e),
+        }
+    }
+}
+
+impl DatabaseError {
+    // Worth connecting again for: the server could not be reached, or is up
+    // but not accepting connections yet. Anything else, a rejected password
+    // or a missing database included, fails the same way next time.
+    fn is_unavailable(&self) -> bool {
+        match self {
+            DatabaseError::Postgres(e) => match e.as_db_error() {
+                Some(db_error) => *db_error.code() == SqlState::CANNOT_CONNECT_NOW,
+                None => e.is_closed() || e.source().is_some_and(|source| source.is::<io::Error>()),
+            },
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DatabaseError::Pool(e) | DatabaseError::SerializationFailure(e) => Some(e.as_ref()),
+            DatabaseError::Constraint(_, e) => e.as_deref().map(|e| e as _),
+            DatabaseError::Postgres(e) => Some(e),
+            #[cfg(all(feature = "sqlite", not(feature = "async")))]
+            DatabaseError::Sqlite(e) => Some(e),
+        }
+    }
+}
+
+// Sorts server-side errors by SQLSTATE so callers can react to the ones
+// caused by the request instead of treating every failure as a 500.
+impl From<PostgresError> for DatabaseError {
+    fn from(error: PostgresError) -> Self {
+        let classified = error.as_db_error().and_then(|db_error| {
+            classify(db_error.code(), db_error.constraint(), db_error.detail(), db_error.column())
+        });
+        match classified {
+            Some(Classified::Constraint(violation)) => DatabaseError::Constraint(violation, Some(Box::new(error))),
+            Some(Classified::SerializationFailure) => DatabaseError::SerializationFailure(Box::new(error)),
+            None => DatabaseError::Postgres(error),
+        }
+    }
+}
+
+// The `DatabaseError` a server-side error turns into, short of its source.
+#[derive(Debug, PartialEq)]
+enum Classified {
+    Constraint(ConstraintViolation),
+    SerializationFailure,
+}
+
+// None for codes that are reported as plain `Postgres` errors.
+fn classify(
+    code: &SqlState,
+    constraint: Option<&str>,
+    detail: Option<&str>,
+    column: Option<&str>,
+) -> Option<Classified> {
+    let constraint = constraint.map(str::to_string);
+    let violation = if *code == SqlState::UNIQUE_VIOLATION {
+        ConstraintViolation::Unique {
+            constraint,
+            columns: detail.map(key_columns).unwrap_or_default(),
+        }
+    } else if *code == SqlState::FOREIGN_KEY_VIOLATION {
+        ConstraintViolation::ForeignKey { constraint }
+    } else if *code == SqlState::CHECK_VIOLATION {
+        ConstraintViolation::Check { constraint }
+    } else if *code == SqlState::NOT_NULL_VIOLATION {
+        ConstraintViolation::NotNull {
+            column: column.map(str::to_string),
+        }
+    } else if *code == SqlState::T_R_SERIALIZATION_FAILURE || *code == SqlState::T_R_DEADLOCK_DETECTED {
+        return Some(Classified::SerializationFailure);
+    } else {
+        return None;
+    };
+
+    Some(Classified::Constraint(violation))
+}
+
+// Pulls the column list out of a detail like `Key (name, email)=(a, b) already exists.`
+fn key_columns(detail: &str) -> Vec<String> {
+    detail
+        .strip_prefix("Key (")
+        .and_then(|rest| rest.split_once(")=("))
+        .map(|(columns, _)| columns.split(", ").map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+impl From<r2d2::Error> for DatabaseError {
+    fn from(error: r2d2::Error) -> Self {
+        DatabaseError::Pool(Box::new(error))
+    }
+}
+
+/// How many connections a pool holds, for metrics.
+pub struct PoolState {
+    pub connections: u32,
+    pub idle: u32,
+    pub max_size: u32,
+}
+
+/// Where users are stored, picked by the scheme of the database URL.
+pub enum Backend {
+    Postgres,
+    // Path of the database file.
+    #[cfg(feature = "sqlite")]
+    Sqlite(String),
+}
+
+impl Backend {
+    pub fn from_url(url: &str) -> Result<Self, String> {
+        match url.strip_prefix("sqlite://").or_else(|| url.strip_prefix("sqlite:")) {
+            #[cfg(feature = "sqlite")]
+            Some(path) if !path.is_empty() => Ok(Backend::Sqlite(path.to_string())),
+            #[cfg(feature = "sqlite")]
+            Some(_) => Err("must name the SQLite file, e.g. sqlite://users.db".to_string()),
+            #[cfg(not(feature = "sqlite"))]
+            Some(_) => Err("points at SQLite, but this build lacks the `sqlite` feature".to_string()),
+            // URLs and key=value connection strings both go to Postgres.
+            None => Ok(Backend::Postgres),
+        }
+    }
+}
+
+// Counts connection attempts and works out the pause before the next one.
+struct Backoff<'a> {
+    retry: &'a RetryConfig,
+    attempt: u32,
+    delay: Duration,
+}
+
+impl<'a> Backoff<'a> {
+    fn new(retry: &'a RetryConfig) -> Self {
+        Backoff {
+            retry,
+            attempt: 1,
+            delay: retry.initial_backoff,
+        }
+    }
+
+    // How long to wait before trying again after `error`, or None to give up.
+    fn after(&mut self, error: &DatabaseError) -> Option<Duration> {
+        if self.attempt >= self.retry.attempts || !error.is_unavailable() {
+            return None;
+        }
+
+        logging::warn!(
+            "database unavailable, retrying",
+            attempt = self.attempt,
+            attempts = self.retry.attempts,
+            retry_in_ms = self.delay.as_millis() as u64,
+            error = logging::error_chain(error),
+        );
+        let delay = self.delay;
+        self.attempt += 1;
+        self.delay = (self.delay * 2).min(self.retry.max_backoff);
+        Some(delay)
+    }
+}
+
+/// Calls `connect` until it succeeds, pausing longer after each attempt, so
+/// that startup can wait out a database that is still coming up. Errors that
+/// another attempt would not fix, such as a bad password, are returned at once.
+pub fn with_retry<T>(
+    retry: &RetryConfig,
+    mut connect: impl FnMut() -> Result<T, DatabaseError>,
+) -> Result<T, DatabaseError> {
+    let mut backoff = Backoff::new(retry);
+    loop {
+        match connect() {
+            Err(e) => match backoff.after(&e) {
+                Some(delay) => thread::sleep(delay),
+                None => return Err(e),
+            },
+            connected => return connected,
+        }
+    }
+}
+
+/// Opens a single connection outside the pool, for one-off work such as
+/// migrations that must not hold a pooled connection.
+pub fn connect_single(config: &DatabaseConfig) -> Result<postgres::Client, DatabaseError> {
+    let postgres_config: postgres::Config = config.url.parse()?;
+    with_retry(&config.retry, || Ok(postgres_config.connect(NoTls)?))
+}
+
+/// Pool of Postgres connections, for the blocking server.
+///
+/// Connections are validated on checkout and broken ones are replaced, so a
+/// restarted database only fails the requests that were in flight.
+#[cfg(not(feature = "async"))]
+#[derive(Clone)]
 pub struct Database {
-    client: Client,
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
 }
 
+#[cfg(not(feature = "async"))]
 impl Database {
-    pub fn new() -> Result<Self, PostgresError> {
-        let database_url = env::var("DATABASE_URL")
-            .expect("DATABASE_URL must be set");
-        
-        let client = Client::connect(&database_url, NoTls)?;
-        
-        Ok(Database { client })
-    }
-
-    pub fn setup_tables(&mut self) -> Result<(), PostgresError> {
-        self.client.batch_execute(
-            "
-            CREATE TABLE IF NOT EXISTS users (
-                id SERIAL PRIMARY KEY,
-                name VARCHAR NOT NULL,
-                email VARCHAR NOT NULL UNIQUE
-            )
-            "
-        )?;
-        Ok(())
-    }
-
-    pub fn get_client(&mut self) -> &mut Client {
-        &mut self.client
+    pub fn new(config: &DatabaseConfig) -> Result<Self, DatabaseError> {
+        // Waits for the database with a plain connection first: r2d2 only
+        // reports that it timed out, which would hide why.
+        drop(connect_single(config)?);
+
+        let postgres_config: postgres::Config = config.url.parse()?;
+        let pool_config = &config.pool;
+        let manager = PostgresConnectionManager::new(postgres_config, NoTls);
+        let pool = r2d2::Pool::builder()
+            .min_idle(Some(pool_config.min_idle))
+            .max_size(pool_config.max_size)
+            .idle_timeout(pool_config.idle_timeout)
+            .connection_timeout(pool_config.connection_timeout)
+            .test_on_check_out(true)
+            .build(manager)?;
+        // With no idle minimum nothing has connected yet.
+        pool.get()?;
+
+        Ok(Database { pool })
+    }
+
+    pub fn get_client(&self) -> Result<PooledClient, DatabaseError> {
+        Ok(self.pool.get()?)
+    }
+
+    pub fn state(&self) -> PoolState {
+        let state = self.pool.state();
+        PoolState {
+            connections: state.connections,
+            idle: state.idle_connections,
+            max_size: self.pool.max_size(),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub type AsyncPooledClient<'a> = bb8::PooledConnection<'a, bb8_postgres::PostgresConnectionManager<NoTls>>;
+
+#[cfg(feature = "async")]
+impl From<bb8::RunError<PostgresError>> for DatabaseError {
+    fn from(error: bb8::RunError<PostgresError>) -> Self {
+        match error {
+            bb8::RunError::User(e) => DatabaseError::from(e),
+            bb8::RunError::TimedOut => DatabaseError::Pool("timed out waiting for a connection".into()),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    pool: bb8::Pool<bb8_postgres::PostgresConnectionManager<NoTls>>,
+    // bb8 does not report the limit it was built with.
+    max_size: u32,
+}
+
+#[cfg(feature = "async")]
+impl AsyncDatabase {
+    pub async fn connect(config: &DatabaseConfig) -> Result<Self, DatabaseError> {
+        let postgres_config: tokio_postgres::Config = config.url.parse()?;
+        let pool_config = &config.pool;
+
+        // Waits for the database with a plain connection first, as
+        // `Database::new` does, so that the error says why it failed.
+        let mut backoff = Backoff::new(&config.retry);
+        while let Err(e) = postgres_config.connect(NoTls).await {
+            let e = DatabaseError::from(e);
+            match backoff.after(&e) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(e),
+            }
+        }
+
+        let manager = bb8_postgres::PostgresConnectionManager::new(postgres_config, NoTls);
+        let pool = bb8::Pool::builder()
+            .min_idle(Some(pool_config.min_idle))
+            .max_size(pool_config.max_size)
+            .idle_timeout(pool_config.idle_timeout)
+            .connection_timeout(pool_config.connection_timeout)
+            .test_on_check_out(true)
+            .build(manager)
+            .await?;
+        // With no idle minimum nothing has connected yet.
+        drop(pool.get().await?);
+
+        Ok(AsyncDatabase {
+            pool,
+            max_size: pool_config.max_size,
+        })
+    }
+
+    pub async fn get_client(&self) -> Result<AsyncPooledClient<'_>, DatabaseError> {
+        Ok(self.pool.get().await?)
+    }
+
+    pub fn state(&self) -> PoolState {
+        let state = self.pool.state();
+        PoolState {
+            connections: state.connections,
+            idle: state.idle_connections,
+            max_size: self.max_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retry(attempts: u32) -> RetryConfig {
+        RetryConfig {
+            attempts,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        }
+    }
+
+    // Nothing listens on port 1, so this fails the way a database that is
+    // not up yet does.
+    fn refused() -> DatabaseError {
+        let config: postgres::Config = "host=127.0.0.1 port=1 user=postgres connect_timeout=5".parse().unwrap();
+        match config.connect(NoTls) {
+            Ok(_) => panic!("something is listening on port 1"),
+            Err(e) => DatabaseError::from(e),
+        }
+    }
+
+    #[test]
+    fn classifies_sqlstates() {
+        let detail = Some("Key (email)=(ada@example.com) already exists.");
+        let cases = [
+            (
+                SqlState::UNIQUE_VIOLATION,
+                Some(Classified::Constraint(ConstraintViolation::Unique {
+                    constraint: Some("users_email_key".to_string()),
+                    columns: vec!["email".to_string()],
+                })),
+            ),
+            (
+                SqlState::FOREIGN_KEY_VIOLATION,
+                Some(Classified::Constraint(ConstraintViolation::ForeignKey {
+                    constraint: Some("users_email_key".to_string()),
+                })),
+            ),
+            (
+                SqlState::CHECK_VIOLATION,
+                Some(Classified::Constraint(ConstraintViolation::Check {
+                    constraint: Some("users_email_key".to_string()),
+                })),
+            ),
+            (
+                SqlState::NOT_NULL_VIOLATION,
+                Some(Classified::Constraint(ConstraintViolation::NotNull {
+                    column: Some("email".to_string()),
+                })),
+            ),
+            (SqlState::T_R_SERIALIZATION_FAILURE, Some(Classified::SerializationFailure)),
+            (SqlState::T_R_DEADLOCK_DETECTED, Some(Classified::SerializationFailure)),
+            (SqlState::UNDEFINED_TABLE, None),
+            (SqlState::CANNOT_CONNECT_NOW, None),
+        ];
+
+        for (code, expected) in cases {
+            assert_eq!(classify(&code, Some("users_email_key"), detail, Some("email")), expected, "{}", code.code());
+        }
+        assert_eq!(
+            classify(&SqlState::UNIQUE_VIOLATION, None, None, None),
+            Some(Classified::Constraint(ConstraintViolation::Unique {
+                constraint: None,
+                columns: Vec::new(),
+            }))
+        );
+    }
+
+    #[test]
+    fn reads_key_columns_from_the_detail() {
+        let cases: [(&str, &[&str]); 6] = [
+            ("Key (email)=(ada@example.com) already exists.", &["email"]),
+            ("Key (name, email)=(Ada, ada@example.com) already exists.", &["name", "email"]),
+            // Values may hold anything, `)=(` included; the columns come first.
+            ("Key (email)=(a)=(b@example.com) already exists.", &["email"]),
+            ("Key (lower(email::text))=(ada@example.com) already exists.", &["lower(email::text)"]),
+            ("Failing row contains (1, Ada).", &[]),
+            ("", &[]),
+        ];
+
+        for (detail, expected) in cases {
+            assert_eq!(key_columns(detail), expected, "{:?}", detail);
+        }
+    }
+
+    #[test]
+    fn only_connection_failures_are_retried() {
+        assert!(refused().is_unavailable());
+
+        let bad_url = "host=127.0.0.1 port=nope".parse::<postgres::Config>().unwrap_err();
+        assert!(!DatabaseError::from(bad_url).is_unavailable());
+        assert!(!DatabaseError::Pool("timed out waiting for a connection".into()).is_unavailable());
+        assert!(!DatabaseError::SerializationFailure("deadlock".into()).is_unavailable());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit_and_gives_up_after_the_attempts() {
+        let retry = retry(6);
+        let mut backoff = Backoff::new(&retry);
+        let error = refused();
+
+        let delays: Vec<Option<u128>> = (0..6).map(|_| backoff.after(&error).map(|delay| delay.as_millis())).collect();
+        assert_eq!(delays, [Some(100), Some(200), Some(400), Some(500), Some(500), None]);
+    }
+
+    #[test]
+    fn backoff_gives_up_at_once_on_other_errors() {
+        assert_eq!(Backoff::new(&retry(6)).after(&DatabaseError::Pool("full".into())), None);
+        assert_eq!(Backoff::new(&retry(1)).after(&refused()), None);
     }
 }
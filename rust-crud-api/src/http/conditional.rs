@@ -0,0 +1,121 @@
+/// Value of an `If-Match` or `If-None-Match` header (RFC 9110 section 13.1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntityTags {
+    // `*`: any current representation.
+    Any,
+    Tags(Vec<EntityTag>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityTag {
+    pub weak: bool,
+    // Without the surrounding quotes.
+    pub opaque: String,
+}
+
+impl EntityTags {
+    // Tags are read up to the first malformed one; the rest is ignored.
+    pub fn parse(value: &str) -> Self {
+        if value.trim() == "*" {
+            return EntityTags::Any;
+        }
+
+        let mut tags = Vec::new();
+        let mut rest = value;
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', ',']);
+            let (weak, tag) = match rest.strip_prefix("W/") {
+                Some(tag) => (true, tag),
+                None => (false, rest),
+            };
+            let opaque = match tag.strip_prefix('"').and_then(|tag| tag.split_once('"')) {
+                Some((opaque, after)) => {
+                    rest = after;
+                    opaque
+                }
+                None => break,
+            };
+            tags.push(EntityTag {
+                weak,
+                opaque: opaque.to_string(),
+            });
+        }
+        EntityTags::Tags(tags)
+    }
+
+    /// Weak comparison, as `If-None-Match` uses: `W/"1"` matches `"1"`.
+    pub fn matches_weak(&self, opaque: &str) -> bool {
+        match self {
+            EntityTags::Any => true,
+            EntityTags::Tags(tags) => tags.iter().any(|tag| tag.opaque == opaque),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strong(opaque: &str) -> EntityTag {
+        EntityTag {
+            weak: false,
+            opaque: opaque.to_string(),
+        }
+    }
+
+    fn weak(opaque: &str) -> EntityTag {
+        EntityTag {
+            weak: true,
+            opaque: opaque.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_header_values() {
+        let cases = [
+            ("*", EntityTags::Any),
+            (" * ", EntityTags::Any),
+            ("\"1\"", EntityTags::Tags(vec![strong("1")])),
+            ("W/\"1\"", EntityTags::Tags(vec![weak("1")])),
+            ("\"\"", EntityTags::Tags(vec![strong("")])),
+            (
+                "\"1\", W/\"2\",\"3\" ,\t\"a b\"",
+                EntityTags::Tags(vec![strong("1"), weak("2"), strong("3"), strong("a b")]),
+            ),
+            (",, \"1\",", EntityTags::Tags(vec![strong("1")])),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(EntityTags::parse(value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn stops_at_the_first_malformed_tag() {
+        let cases = [
+            ("", vec![]),
+            ("1", vec![]),
+            ("W/1", vec![]),
+            ("w/\"1\"", vec![]),
+            ("\"unterminated", vec![]),
+            // `*` only means "any" on its own.
+            ("*, \"1\"", vec![]),
+            ("\"1\", *", vec![strong("1")]),
+            ("\"1\", bogus, \"2\"", vec![strong("1")]),
+            ("\"1\" \"2\"", vec![strong("1"), strong("2")]),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(EntityTags::parse(value), EntityTags::Tags(expected), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn weak_comparison_ignores_the_weak_flag() {
+        assert!(EntityTags::Any.matches_weak("7"));
+        assert!(EntityTags::parse("W/\"7\"").matches_weak("7"));
+        assert!(EntityTags::parse("\"1\", \"7\"").matches_weak("7"));
+        assert!(!EntityTags::parse("\"1\", \"17\"").matches_weak("7"));
+        assert!(!EntityTags::parse("7").matches_weak("7"));
+    }
+}
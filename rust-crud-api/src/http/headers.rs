@@ -0,0 +1,40 @@
+/// Header list that keeps insertion order and casing, with case-insensitive
+/// lookups. Used for both request and response headers.
+#[derive(Debug, Clone, Default)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn append(&mut self, name: String, value: String) {
+        self.entries.push((name, value));
+    }
+
+    /// Replaces every existing value of `name` with `value`.
+    pub fn set(&mut self, name: &str, value: String) {
+        self.entries.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.entries.push((name.to_string(), value));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
@@ -0,0 +1,17 @@
+pub mod conditional;
+pub mod headers;
+pub mod parser;
+pub mod problem;
+pub mod query;
+pub mod request;
+pub mod response;
+pub mod router;
+
+pub use conditional::EntityTags;
+pub use headers::Headers;
+pub use parser::{ParseError, ParserLimits, RequestParser};
+pub use problem::Problem;
+pub use query::Query;
+pub use request::{Method, Request};
+pub use response::{Response, StatusCode};
+pub use router::{PathParams, RouteMatch, Router, TrailingSlash};
@@ -0,0 +1,565 @@
+use crate::http::request::{Method, Request, Version};
+use crate::http::{Headers, PathParams, Query};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParserLimits {
+    pub max_header_bytes: usize,
+    pub max_body_bytes: usize,
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: 8 * 1024,
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Malformed(&'static str),
+    UnsupportedMethod,
+    UnsupportedVersion,
+    HeadersTooLarge,
+    BodyTooLarge,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Malformed(reason) => write!(f, "malformed request: {}", reason),
+            ParseError::UnsupportedMethod => write!(f, "unsupported method"),
+            ParseError::UnsupportedVersion => write!(f, "unsupported HTTP version"),
+            ParseError::HeadersTooLarge => write!(f, "request headers too large"),
+            ParseError::BodyTooLarge => write!(f, "request body too large"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+struct Head {
+    method: Method,
+    target: String,
+    version: Version,
+    headers: Headers,
+    body: BodyKind,
+    // Offset of the first byte after the blank line ending the headers.
+    len: usize,
+    // Whether the client waits for `100 Continue` before sending the body.
+    expects_continue: bool,
+}
+
+enum BodyKind {
+    Empty,
+    Length(usize),
+    Chunked(Chunked),
+}
+
+// Longest chunk-size or trailer line accepted, extensions included.
+const MAX_CHUNK_LINE: usize = 4096;
+
+// How far a chunked body has been decoded, kept between reads so that each
+// byte is only looked at once.
+#[derive(Default)]
+struct Chunked {
+    body: Vec<u8>,
+    // Offset, from the end of the head, of the next chunk-size or trailer line.
+    pos: usize,
+    // Set once the last chunk is in and only trailer fields remain.
+    in_trailers: bool,
+}
+
+/// Incremental HTTP/1.1 request parser.
+///
+/// Bytes are pushed in with `feed` as they arrive from the socket and `parse`
+/// is called after every read; it returns `Ok(None)` until a whole request
+/// (head and body) is buffered. The parser does no I/O of its own.
+pub struct RequestParser {
+    limits: ParserLimits,
+    buffer: Vec<u8>,
+    head: Option<Head>,
+}
+
+impl RequestParser {
+    pub fn new(limits: ParserLimits) -> Self {
+        Self {
+            limits,
+            buffer: Vec::new(),
+            head: None,
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// True, once, when the head of a request whose client waits for
+    /// `100 Continue` has been parsed but its body has not arrived.
+    pub fn take_continue(&mut self) -> bool {
+        self.head
+            .as_mut()
+            .is_some_and(|head| std::mem::take(&mut head.expects_continue))
+    }
+
+    pub fn parse(&mut self) -> Result<Option<Request>, ParseError> {
+        if self.head.is_none() {
+            match self.parse_head()? {
+                Some(head) => self.head = Some(head),
+                None => return Ok(None),
+            }
+        }
+
+        let head = self.head.as_mut().expect("head parsed above");
+        let available = &self.buffer[head.len..];
+
+        let (body, consumed) = match &mut head.body {
+            BodyKind::Empty => (Vec::new(), 0),
+            BodyKind::Length(length) => {
+                let length = *length;
+                if available.len() < length {
+                    return Ok(None);
+                }
+                (available[..length].to_vec(), length)
+            }
+            BodyKind::Chunked(chunked) => match chunked.decode(available, self.limits.max_body_bytes)? {
+                Some(consumed) => (std::mem::take(&mut chunked.body), consumed),
+                None => {
+                    // Framing overhead is small, so a buffer this large that still
+                    // has no final chunk can only be an oversized body.
+                    if available.len() > self.limits.max_body_bytes + self.limits.max_header_bytes {
+                        return Err(ParseError::BodyTooLarge);
+                    }
+                    return Ok(None);
+                }
+            },
+        };
+
+        let head = self.head.take().expect("head parsed above");
+        self.buffer.drain(..head.len + consumed);
+
+        let (path, query) = match head.target.split_once('?') {
+            Some((path, query)) => (path.to_string(), Query::parse(query)),
+            None => (head.target, Query::default()),
+        };
+
+        Ok(Some(Request {
+            method: head.method,
+            path,
+            query,
+            version: head.version,
+            headers: head.headers,
+            body,
+            params: PathParams::default(),
+        }))
+    }
+
+    fn parse_head(&self) -> Result<Option<Head>, ParseError> {
+        let end = match find_subsequence(&self.buffer, b"\r\n\r\n") {
+            Some(end) => end,
+            None => {
+                if self.buffer.len() > self.limits.max_header_bytes {
+                    return Err(ParseError::HeadersTooLarge);
+                }
+                return Ok(None);
+            }
+        };
+
+        if end + 4 > self.limits.max_header_bytes {
+            return Err(ParseError::HeadersTooLarge);
+        }
+
+        let text = std::str::from_utf8(&self.buffer[..end])
+            .map_err(|_| ParseError::Malformed("request head is not valid UTF-8"))?;
+        let mut lines = text.split("\r\n");
+
+        let request_line = lines.next().unwrap_or_default();
+        let mut parts = request_line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version), None) => (method, target, version),
+            _ => return Err(ParseError::Malformed("invalid request line")),
+        };
+
+        let method = Method::parse(method).ok_or(ParseError::UnsupportedMethod)?;
+        let version = Version::parse(version).ok_or(ParseError::UnsupportedVersion)?;
+        if !target.starts_with('/') {
+            return Err(ParseError::Malformed("request target must be an absolute path"));
+        }
+
+        let mut headers = Headers::new();
+        for line in lines {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(ParseError::Malformed("invalid header line"))?;
+            if name.is_empty() || name.ends_with(|c: char| c.is_ascii_whitespace()) {
+                return Err(ParseError::Malformed("invalid header name"));
+            }
+            headers.append(name.to_string(), value.trim().to_string());
+        }
+
+        // HTTP/1.1 clients must always identify the host (RFC 9112 section 3.2).
+        if version == Version::Http11 && headers.get("Host").is_none() {
+            return Err(ParseError::Malformed("missing Host header"));
+        }
+
+        let body = body_kind(&headers, self.limits.max_body_bytes)?;
+        // HTTP/1.0 clients cannot be sent a 1xx response (RFC 9110 section 10.1.1).
+        let expects_continue = version == Version::Http11
+            && !matches!(body, BodyKind::Empty)
+            && headers
+                .get("Expect")
+                .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"));
+
+        Ok(Some(Head {
+            method,
+            target: target.to_string(),
+            version,
+            headers,
+            body,
+            len: end + 4,
+            expects_continue,
+        }))
+    }
+}
+
+fn body_kind(headers: &Headers, max_body_bytes: usize) -> Result<BodyKind, ParseError> {
+    let mut codings = headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty())
+        .peekable();
+
+    if codings.peek().is_some() {
+        // Only chunked is understood, and per RFC 9112 it must be the final coding.
+        let codings: Vec<&str> = codings.collect();
+        if codings.len() != 1 || !codings[0].eq_ignore_ascii_case("chunked") {
+            return Err(ParseError::Malformed("unsupported transfer encoding"));
+        }
+        if headers.get("Content-Length").is_some() {
+            return Err(ParseError::Malformed("both Transfer-Encoding and Content-Length present"));
+        }
+        return Ok(BodyKind::Chunked(Chunked::default()));
+    }
+
+    let mut lengths = headers.get_all("Content-Length");
+    match lengths.next() {
+        None => Ok(BodyKind::Empty),
+        Some(value) => {
+            if lengths.any(|other| other != value) {
+                return Err(ParseError::Malformed("conflicting Content-Length headers"));
+            }
+            // Only digits: `parse` alone would also take a leading `+`.
+            let length: usize = value
+                .parse()
+                .ok()
+                .filter(|_| value.bytes().all(|b| b.is_ascii_digit()))
+                .ok_or(ParseError::Malformed("invalid Content-Length"))?;
+            if length > max_body_bytes {
+                return Err(ParseError::BodyTooLarge);
+            }
+            if length == 0 {
+                Ok(BodyKind::Empty)
+            } else {
+                Ok(BodyKind::Length(length))
+            }
+        }
+    }
+}
+
+impl Chunked {
+    // Decodes whatever has arrived since the last call. Returns the number of
+    // buffered bytes the body occupied once the final chunk and trailers are
+    // in, or `None` until then.
+    fn decode(&mut self, input: &[u8], max_body_bytes: usize) -> Result<Option<usize>, ParseError> {
+        loop {
+            let line_end = match find_line(input, self.pos)? {
+                Some(line_end) => line_end,
+                None => return Ok(None),
+            };
+
+            if self.in_trailers {
+                // Trailer fields are skipped up to the terminating blank line.
+                let empty = line_end == self.pos;
+                self.pos = line_end + 2;
+                if empty {
+                    return Ok(Some(self.pos));
+                }
+                continue;
+            }
+
+            let size_line = std::str::from_utf8(&input[self.pos..line_end])
+                .map_err(|_| ParseError::Malformed("invalid chunk size"))?;
+            // Chunk extensions are allowed after ';' and ignored.
+            let size_text = size_line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size_text, 16)
+                .map_err(|_| ParseError::Malformed("invalid chunk size"))?;
+            let data = line_end + 2;
+
+            if size == 0 {
+                self.in_trailers = true;
+                self.pos = data;
+                continue;
+            }
+
+            // A size near usize::MAX must not wrap around the limit check.
+            if self.body.len().checked_add(size).is_none_or(|length| length > max_body_bytes) {
+                return Err(ParseError::BodyTooLarge);
+            }
+            let data_end = data.checked_add(size).ok_or(ParseError::BodyTooLarge)?;
+            let chunk_end = data_end.checked_add(2).ok_or(ParseError::BodyTooLarge)?;
+            if input.len() < chunk_end {
+                return Ok(None);
+            }
+            if &input[data_end..chunk_end] != b"\r\n" {
+                return Err(ParseError::Malformed("chunk not terminated by CRLF"));
+            }
+            self.body.extend_from_slice(&input[data..data_end]);
+            self.pos = chunk_end;
+        }
+    }
+}
+
+// Finds the CRLF ending the line that starts at `start`, if it has arrived.
+fn find_line(input: &[u8], start: usize) -> Result<Option<usize>, ParseError> {
+    match find_subsequence(&input[start..], b"\r\n") {
+        Some(offset) => Ok(Some(start + offset)),
+        None if input.len() - start > MAX_CHUNK_LINE => Err(ParseError::Malformed("chunk line too long")),
+        None => Ok(None),
+    }
+}
+
+fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::StatusCode;
+    use crate::server::parse_error_response;
+
+    const LIMITS: ParserLimits = ParserLimits {
+        max_header_bytes: 256,
+        max_body_bytes: 64,
+    };
+
+    fn parse_all(input: &[u8]) -> Result<Option<Request>, ParseError> {
+        let mut parser = RequestParser::new(LIMITS);
+        parser.feed(input);
+        parser.parse()
+    }
+
+    // Feeds one byte at a time and expects nothing until the last one.
+    fn parse_bytewise(input: &[u8]) -> Request {
+        let mut parser = RequestParser::new(LIMITS);
+        for (i, byte) in input.iter().enumerate() {
+            parser.feed(&[*byte]);
+            let parsed = parser.parse().unwrap();
+            if i + 1 < input.len() {
+                assert!(parsed.is_none(), "request complete after {} of {} bytes", i + 1, input.len());
+            } else {
+                assert!(parser.is_empty());
+                return parsed.expect("request complete after the last byte");
+            }
+        }
+        unreachable!("input is not empty")
+    }
+
+    fn status(error: ParseError) -> StatusCode {
+        parse_error_response(&error).status
+    }
+
+    #[test]
+    fn parses_a_request_split_at_every_byte() {
+        let request = parse_bytewise(
+            b"POST /users?limit=5 HTTP/1.1\r\nHost: x\r\nContent-Length: 12\r\nX-Empty:\r\n\r\n{\"name\":\"a\"}",
+        );
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.path, "/users");
+        assert_eq!(request.query.get("limit"), Some("5"));
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.headers.get("content-length"), Some("12"));
+        assert_eq!(request.headers.get("X-Empty"), Some(""));
+        assert_eq!(request.body, b"{\"name\":\"a\"}");
+    }
+
+    #[test]
+    fn parses_a_chunked_body_split_at_every_byte() {
+        let request = parse_bytewise(
+            b"PUT /users/1 HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+              4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nTrailer: yes\r\n\r\n",
+        );
+        assert_eq!(request.body, b"Wikipedia");
+    }
+
+    #[test]
+    fn parses_pipelined_requests_in_order() {
+        let mut parser = RequestParser::new(LIMITS);
+        parser.feed(b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nPOST /b HTTP/1.1\r\nHost: x\r\nContent-Length: 2\r\n\r\nhi");
+        parser.feed(b"GET /c HTTP/1.0\r\n\r\n");
+
+        let paths: Vec<String> = std::iter::from_fn(|| parser.parse().unwrap()).map(|request| request.path).collect();
+        assert_eq!(paths, ["/a", "/b", "/c"]);
+        assert!(parser.is_empty());
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let head = "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n";
+        // Ok(None) means the parser waits for more.
+        type Body = Result<Option<&'static [u8]>, ParseError>;
+        let cases: &[(&str, Body)] = &[
+            ("0\r\n\r\n", Ok(Some(b""))),
+            ("A\r\n0123456789\r\n0\r\n\r\n", Ok(Some(b"0123456789"))),
+            ("a ; name=\"value\"\r\nabcdefghij\r\n0\r\n\r\n", Ok(Some(b"abcdefghij"))),
+            ("3\r\nabc\r\n0\r\nA: 1\r\nB: 2\r\n\r\n", Ok(Some(b"abc"))),
+            ("3\r\nabc\r\n0\r\nA: 1\r\n", Ok(None)),
+            ("40\r\n", Ok(None)),
+            ("x\r\nabc\r\n0\r\n\r\n", Err(ParseError::Malformed("invalid chunk size"))),
+            ("-3\r\nabc\r\n0\r\n\r\n", Err(ParseError::Malformed("invalid chunk size"))),
+            ("3\r\nabcd\r\n0\r\n\r\n", Err(ParseError::Malformed("chunk not terminated by CRLF"))),
+            ("41\r\n", Err(ParseError::BodyTooLarge)),
+            // Sizes that would overflow the length arithmetic.
+            ("ffffffffffffffff\r\n", Err(ParseError::BodyTooLarge)),
+            ("1\r\na\r\nffffffffffffffff\r\n", Err(ParseError::BodyTooLarge)),
+        ];
+
+        for (body, expected) in cases {
+            let parsed = parse_all(format!("{}{}", head, body).as_bytes()).map(|request| request.map(|r| r.body));
+            assert_eq!(parsed.as_ref().map(|body| body.as_deref()), expected.as_ref().copied(), "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn a_chunked_body_is_limited_in_total() {
+        let mut parser = RequestParser::new(LIMITS);
+        parser.feed(b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n");
+        for _ in 0..4 {
+            parser.feed(b"10\r\n0123456789abcdef\r\n");
+            assert!(parser.parse().unwrap().is_none());
+        }
+        parser.feed(b"1\r\nx\r\n");
+        assert_eq!(parser.parse().unwrap_err(), ParseError::BodyTooLarge);
+    }
+
+    #[test]
+    fn a_chunk_size_line_is_limited() {
+        let mut parser = RequestParser::new(LIMITS);
+        parser.feed(b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n1;");
+        parser.feed(&[b'a'; MAX_CHUNK_LINE]);
+        assert_eq!(parser.parse().unwrap_err(), ParseError::Malformed("chunk line too long"));
+    }
+
+    #[test]
+    fn enforces_the_header_limit() {
+        let padding = "a".repeat(LIMITS.max_header_bytes);
+
+        // Too large before the end of the head has even arrived.
+        let error = parse_all(format!("GET / HTTP/1.1\r\nX-Pad: {}", padding).as_bytes()).unwrap_err();
+        assert_eq!(error, ParseError::HeadersTooLarge);
+        assert_eq!(status(error), StatusCode::RequestHeaderFieldsTooLarge);
+
+        let error = parse_all(format!("GET / HTTP/1.1\r\nX-Pad: {}\r\n\r\n", padding).as_bytes()).unwrap_err();
+        assert_eq!(error, ParseError::HeadersTooLarge);
+
+        // Exactly at the limit is fine.
+        let head = "GET / HTTP/1.1\r\nHost: x\r\nX-Pad: \r\n\r\n";
+        let pad = "a".repeat(LIMITS.max_header_bytes - head.len());
+        let request = format!("GET / HTTP/1.1\r\nHost: x\r\nX-Pad: {}\r\n\r\n", pad);
+        assert!(parse_all(request.as_bytes()).unwrap().is_some());
+    }
+
+    #[test]
+    fn enforces_the_body_limit() {
+        let at_limit = format!("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 64\r\n\r\n{}", "a".repeat(64));
+        assert_eq!(parse_all(at_limit.as_bytes()).unwrap().unwrap().body.len(), 64);
+
+        // Rejected from the header alone, before any of the body is read.
+        let error = parse_all(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 65\r\n\r\n").unwrap_err();
+        assert_eq!(error, ParseError::BodyTooLarge);
+        assert_eq!(status(error), StatusCode::PayloadTooLarge);
+
+        let error = parse_all(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 99999999999999999999999\r\n\r\n");
+        assert_eq!(error.unwrap_err(), ParseError::Malformed("invalid Content-Length"));
+    }
+
+    #[test]
+    fn checks_content_length_and_transfer_encoding() {
+        let cases: &[(&str, Result<&[u8], ParseError>)] = &[
+            ("Content-Length: 2\r\nContent-Length: 2", Ok(b"hi")),
+            ("Content-Length: 2\r\nContent-Length: 3", Err(ParseError::Malformed("conflicting Content-Length headers"))),
+            ("Content-Length: 2, 2", Err(ParseError::Malformed("invalid Content-Length"))),
+            ("Content-Length: +2", Err(ParseError::Malformed("invalid Content-Length"))),
+            ("Content-Length: -1", Err(ParseError::Malformed("invalid Content-Length"))),
+            (
+                "Transfer-Encoding: chunked\r\nContent-Length: 2",
+                Err(ParseError::Malformed("both Transfer-Encoding and Content-Length present")),
+            ),
+            ("Transfer-Encoding: gzip, chunked", Err(ParseError::Malformed("unsupported transfer encoding"))),
+            ("Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked", Err(ParseError::Malformed("unsupported transfer encoding"))),
+        ];
+
+        for (headers, expected) in cases {
+            let request = format!("POST / HTTP/1.1\r\nHost: x\r\n{}\r\n\r\nhi", headers);
+            match (parse_all(request.as_bytes()), expected) {
+                (Ok(Some(request)), Ok(body)) => assert_eq!(request.body, *body, "{:?}", headers),
+                (Err(error), Err(expected)) => assert_eq!(error, *expected, "{:?}", headers),
+                (parsed, _) => panic!("{:?}: unexpected {:?}", headers, parsed.map(|r| r.map(|r| r.body))),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        let cases: &[(&[u8], ParseError)] = &[
+            (b"GET /\r\n\r\n", ParseError::Malformed("invalid request line")),
+            (b"GET  / HTTP/1.1\r\nHost: x\r\n\r\n", ParseError::Malformed("invalid request line")),
+            (b"BREW / HTTP/1.1\r\nHost: x\r\n\r\n", ParseError::UnsupportedMethod),
+            (b"GET / HTTP/2.0\r\nHost: x\r\n\r\n", ParseError::UnsupportedVersion),
+            (b"GET users HTTP/1.1\r\nHost: x\r\n\r\n", ParseError::Malformed("request target must be an absolute path")),
+            (b"GET / HTTP/1.1\r\n\r\n", ParseError::Malformed("missing Host header")),
+            (b"GET / HTTP/1.1\r\nHost: x\r\nNo colon\r\n\r\n", ParseError::Malformed("invalid header line")),
+            (b"GET / HTTP/1.1\r\nHost : x\r\n\r\n", ParseError::Malformed("invalid header name")),
+            (b"GET / HTTP/1.1\r\nHost: \xff\r\n\r\n", ParseError::Malformed("request head is not valid UTF-8")),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_all(input).unwrap_err(), *expected, "{:?}", String::from_utf8_lossy(input));
+        }
+    }
+
+    #[test]
+    fn asks_for_the_body_once_when_the_client_expects_100_continue() {
+        let mut parser = RequestParser::new(LIMITS);
+        parser.feed(b"POST / HTTP/1.1\r\nHost: x\r\nExpect: 100-Continue\r\nContent-Length: 2\r\n\r\n");
+        assert!(parser.parse().unwrap().is_none());
+        assert!(parser.take_continue());
+        assert!(!parser.take_continue());
+
+        parser.feed(b"hi");
+        assert_eq!(parser.parse().unwrap().unwrap().body, b"hi");
+        assert!(!parser.take_continue());
+
+        // Not for bodiless requests, HTTP/1.0 clients or bodies already sent.
+        for input in [
+            &b"GET / HTTP/1.1\r\nHost: x\r\nExpect: 100-continue\r\n\r\n"[..],
+            b"POST / HTTP/1.0\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\n",
+        ] {
+            let mut parser = RequestParser::new(LIMITS);
+            parser.feed(input);
+            let _ = parser.parse().unwrap();
+            assert!(!parser.take_continue());
+        }
+        let mut parser = RequestParser::new(LIMITS);
+        parser.feed(b"POST / HTTP/1.1\r\nHost: x\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\nhi");
+        assert!(parser.parse().unwrap().is_some());
+        assert!(!parser.take_continue());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::http::{Response, StatusCode};
+use crate::validation::FieldError;
+use serde::Serialize;
+
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Error body in the RFC 7807 `application/problem+json` format.
+///
+/// `type` is left as `about:blank`, so `title` is the reason phrase of the
+/// status. Clients should branch on the `code` extension member, which is
+/// stable across releases, rather than on `title` or `detail`.
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+    #[serde(skip)]
+    http_status: StatusCode,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, code: &'static str) -> Self {
+        Self {
+            problem_type: "about:blank",
+            title: status.reason_phrase(),
+            status: status.as_u16(),
+            code,
+            detail: None,
+            errors: Vec::new(),
+            http_status: status,
+        }
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    pub fn into_response(self) -> Response {
+        Response::json(self.http_status, &self).with_header("Content-Type", PROBLEM_CONTENT_TYPE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(response: &Response) -> String {
+        String::from_utf8(response.body.clone()).unwrap()
+    }
+
+    #[test]
+    fn serializes_the_standard_members() {
+        let response = Problem::new(StatusCode::NotFound, "user_not_found")
+            .detail("User 7 not found")
+            .into_response();
+
+        assert_eq!(response.status, StatusCode::NotFound);
+        assert_eq!(response.headers.get("Content-Type"), Some("application/problem+json"));
+        // No `instance`: the request path is already in the access log.
+        assert_eq!(
+            body(&response),
+            r#"{"type":"about:blank","title":"Not Found","status":404,"code":"user_not_found","detail":"User 7 not found"}"#
+        );
+    }
+
+    #[test]
+    fn leaves_out_empty_members() {
+        let response = Problem::new(StatusCode::ServiceUnavailable, "server_busy").into_response();
+        assert_eq!(
+            body(&response),
+            r#"{"type":"about:blank","title":"Service Unavailable","status":503,"code":"server_busy"}"#
+        );
+    }
+
+    #[test]
+    fn lists_field_errors_as_an_extension_member() {
+        let errors = vec![
+            FieldError {
+                field: "name",
+                code: "required",
+                message: "name must not be empty".to_string(),
+            },
+            FieldError {
+                field: "email",
+                code: "invalid_format",
+                message: "email must be a valid email address".to_string(),
+            },
+        ];
+        let response = Problem::new(StatusCode::UnprocessableEntity, "validation_failed")
+            .detail("One or more fields are invalid")
+            .errors(errors)
+            .into_response();
+
+        assert_eq!(response.status, StatusCode::UnprocessableEntity);
+        assert_eq!(response.headers.get("Content-Type"), Some("application/problem+json"));
+        assert_eq!(
+            body(&response),
+            concat!(
+                r#"{"type":"about:blank","title":"Unprocessable Entity","status":422,"code":"validation_failed","#,
+                r#""detail":"One or more fields are invalid","errors":["#,
+                r#"{"field":"name","code":"required","message":"name must not be empty"},"#,
+                r#"{"field":"email","code":"invalid_format","message":"email must be a valid email address"}]}"#
+            )
+        );
+    }
+}
@@ -0,0 +1,105 @@
+/// Decoded `application/x-www-form-urlencoded` query string. The raw form is
+/// kept so redirects can pass it on unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    raw: String,
+    pairs: Vec<(String, String)>,
+}
+
+impl Query {
+    pub fn parse(raw: &str) -> Self {
+        let pairs = raw
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((name, value)) => (decode(name), decode(value)),
+                None => (decode(pair), String::new()),
+            })
+            .collect();
+
+        Self {
+            raw: raw.to_string(),
+            pairs,
+        }
+    }
+
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    // The first value wins if a name is repeated.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Builds a query string from name/value pairs, escaping everything except
+/// RFC 3986 unreserved characters and the comma used in lists like `sort`.
+pub fn encode_pairs<'a>(pairs: impl IntoIterator<Item = (&'a str, String)>) -> String {
+    pairs
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", encode(name), encode(&value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b',' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// `+` is a space and `%XX` a byte; malformed escapes are kept literally and
+// invalid UTF-8 is replaced rather than rejected.
+fn decode(text: &str) -> String {
+    percent_decode(text, true)
+}
+
+/// Decodes one segment of a request path, which is like a query value except
+/// that `+` stands for itself.
+pub fn decode_segment(text: &str) -> String {
+    percent_decode(text, false)
+}
+
+fn percent_decode(text: &str, plus_is_space: bool) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = match bytes.get(i + 1..i + 3) {
+            Some(&[high, low]) if bytes[i] == b'%' => hex_value(high).zip(hex_value(low)),
+            _ => None,
+        };
+
+        match (bytes[i], escaped) {
+            (_, Some((high, low))) => {
+                decoded.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+            (b'+', None) if plus_is_space => decoded.push(b' '),
+            (byte, None) => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
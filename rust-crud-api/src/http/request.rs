@@ -0,0 +1,101 @@
+use crate::http::{EntityTags, Headers, PathParams, Query};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Patch,
+    Delete,
+    Options,
+}
+
+impl Method {
+    pub fn parse(token: &str) -> Option<Self> {
+        match token {
+            "GET" => Some(Method::Get),
+            "HEAD" => Some(Method::Head),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "PATCH" => Some(Method::Patch),
+            "DELETE" => Some(Method::Delete),
+            "OPTIONS" => Some(Method::Options),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Patch => "PATCH",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn parse(token: &str) -> Option<Self> {
+        match token {
+            "HTTP/1.0" => Some(Version::Http10),
+            "HTTP/1.1" => Some(Version::Http11),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    pub query: Query,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    // Filled in by the router once the request has matched a route.
+    pub params: PathParams,
+}
+
+impl Request {
+    // HTTP/1.1 connections persist unless the client says otherwise; HTTP/1.0
+    // ones only when the client explicitly asks.
+    pub fn wants_keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.headers
+                .get_all("Connection")
+                .flat_map(|value| value.split(','))
+                .any(|option| option.trim().eq_ignore_ascii_case(token))
+        };
+
+        match self.version {
+            Version::Http11 => !has_token("close"),
+            Version::Http10 => has_token("keep-alive"),
+        }
+    }
+
+    // `If-Match` / `If-None-Match`, with repeated headers read as one list.
+    pub fn entity_tags(&self, name: &str) -> Option<EntityTags> {
+        let values: Vec<&str> = self.headers.get_all(name).collect();
+        if values.is_empty() {
+            return None;
+        }
+        Some(EntityTags::parse(&values.join(",")))
+    }
+}
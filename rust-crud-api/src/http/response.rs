@@ -0,0 +1,276 @@
+use crate::http::Headers;
+use serde::Serialize;
+use std::error::Error;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusCode {
+    Ok,
+    Created,
+    NotModified,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RequestTimeout,
+    Conflict,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    UnprocessableEntity,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    ServiceUnavailable,
+    HttpVersionNotSupported,
+}
+
+impl StatusCode {
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            StatusCode::Ok => 200,
+            StatusCode::Created => 201,
+            StatusCode::NotModified => 304,
+            StatusCode::PermanentRedirect => 308,
+            StatusCode::BadRequest => 400,
+            StatusCode::Unauthorized => 401,
+            StatusCode::Forbidden => 403,
+            StatusCode::NotFound => 404,
+            StatusCode::MethodNotAllowed => 405,
+            StatusCode::RequestTimeout => 408,
+            StatusCode::Conflict => 409,
+            StatusCode::PreconditionFailed => 412,
+            StatusCode::PayloadTooLarge => 413,
+            StatusCode::UnsupportedMediaType => 415,
+            StatusCode::UnprocessableEntity => 422,
+            StatusCode::RequestHeaderFieldsTooLarge => 431,
+            StatusCode::InternalServerError => 500,
+            StatusCode::NotImplemented => 501,
+            StatusCode::ServiceUnavailable => 503,
+            StatusCode::HttpVersionNotSupported => 505,
+        }
+    }
+
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            StatusCode::Ok => "OK",
+            StatusCode::Created => "Created",
+            StatusCode::NotModified => "Not Modified",
+            StatusCode::PermanentRedirect => "Permanent Redirect",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::Unauthorized => "Unauthorized",
+            StatusCode::Forbidden => "Forbidden",
+            StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::RequestTimeout => "Request Timeout",
+            StatusCode::Conflict => "Conflict",
+            StatusCode::PreconditionFailed => "Precondition Failed",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            StatusCode::UnprocessableEntity => "Unprocessable Entity",
+            StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::NotImplemented => "Not Implemented",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+            StatusCode::HttpVersionNotSupported => "HTTP Version Not Supported",
+        }
+    }
+
+    // 304 responses never carry a body (RFC 9110 section 15.4.5).
+    fn allows_body(&self) -> bool {
+        !matches!(self, StatusCode::NotModified)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    // What went wrong behind a 500, for the server to log; never sent.
+    pub cause: Option<Arc<dyn Error + Send + Sync>>,
+}
+
+impl Response {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: Headers::new(),
+            body: Vec::new(),
+            cause: None,
+        }
+    }
+
+    pub fn bytes(status: StatusCode, content_type: &str, body: Vec<u8>) -> Self {
+        Self::new(status)
+            .with_header("Content-Type", content_type)
+            .with_body(body)
+    }
+
+    pub fn text(status: StatusCode, text: &str) -> Self {
+        Self::bytes(status, "text/plain; charset=utf-8", text.as_bytes().to_vec())
+    }
+
+    pub fn json<T: Serialize + ?Sized>(status: StatusCode, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self::bytes(status, "application/json", body),
+            Err(_) => Self::text(StatusCode::InternalServerError, "JSON serialization error"),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.set(name, value.into());
+        self
+    }
+
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    pub fn with_cause(mut self, cause: impl Error + Send + Sync + 'static) -> Self {
+        self.cause = Some(Arc::new(cause));
+        self
+    }
+
+    /// Serialises the response for the wire. The server owns the framing
+    /// headers, so `Content-Length` and `Connection` are always written here
+    /// rather than taken from `headers`. With `include_body` false (HEAD) the
+    /// headers still describe the body that a GET would have returned.
+    pub fn to_bytes(&self, keep_alive: bool, include_body: bool) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status.as_u16(), self.status.reason_phrase());
+
+        for (name, value) in self.headers.iter() {
+            if name.eq_ignore_ascii_case("Content-Length") || name.eq_ignore_ascii_case("Connection") {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        // A 304 that sent a length would have to send the length of the
+        // representation it stands for, so it sends none.
+        if self.status.allows_body() {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str(if keep_alive { "Connection: keep-alive\r\n" } else { "Connection: close\r\n" });
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        if include_body && self.status.allows_body() {
+            bytes.extend_from_slice(&self.body);
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn wire(response: &Response, keep_alive: bool, include_body: bool) -> String {
+        String::from_utf8(response.to_bytes(keep_alive, include_body)).unwrap()
+    }
+
+    #[test]
+    fn writes_the_status_line_headers_and_body() {
+        let response = Response::json(StatusCode::Created, &[1, 2, 3]).with_header("ETag", "\"1\"");
+        assert_eq!(
+            wire(&response, true, true),
+            "HTTP/1.1 201 Created\r\n\
+             Content-Type: application/json\r\n\
+             ETag: \"1\"\r\n\
+             Content-Length: 7\r\n\
+             Connection: keep-alive\r\n\
+             \r\n\
+             [1,2,3]"
+        );
+        assert_eq!(
+            wire(&Response::new(StatusCode::NotFound), false, true),
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn framing_headers_set_by_handlers_are_replaced() {
+        let response = Response::text(StatusCode::Ok, "hello")
+            .with_header("content-length", "999")
+            .with_header("CONNECTION", "upgrade")
+            .with_header("X-Other", "kept");
+        assert_eq!(
+            wire(&response, false, true),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             X-Other: kept\r\n\
+             Content-Length: 5\r\n\
+             Connection: close\r\n\
+             \r\n\
+             hello"
+        );
+    }
+
+    #[test]
+    fn head_responses_describe_the_body_without_sending_it() {
+        let response = Response::text(StatusCode::Ok, "hello");
+        assert_eq!(
+            wire(&response, true, false),
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: 5\r\n\
+             Connection: keep-alive\r\n\
+             \r\n"
+        );
+    }
+
+    #[test]
+    fn not_modified_sends_neither_a_length_nor_a_body() {
+        let response = Response::new(StatusCode::NotModified)
+            .with_header("ETag", "\"3\"")
+            .with_body(b"ignored".to_vec());
+        let expected = "HTTP/1.1 304 Not Modified\r\nETag: \"3\"\r\nConnection: keep-alive\r\n\r\n";
+        assert_eq!(wire(&response, true, true), expected);
+        assert_eq!(wire(&response, true, false), expected);
+    }
+
+    #[test]
+    fn every_status_has_its_code_and_reason_phrase() {
+        let cases = [
+            (StatusCode::Ok, "200 OK"),
+            (StatusCode::Created, "201 Created"),
+            (StatusCode::NotModified, "304 Not Modified"),
+            (StatusCode::PermanentRedirect, "308 Permanent Redirect"),
+            (StatusCode::BadRequest, "400 Bad Request"),
+            (StatusCode::Unauthorized, "401 Unauthorized"),
+            (StatusCode::Forbidden, "403 Forbidden"),
+            (StatusCode::NotFound, "404 Not Found"),
+            (StatusCode::MethodNotAllowed, "405 Method Not Allowed"),
+            (StatusCode::RequestTimeout, "408 Request Timeout"),
+            (StatusCode::Conflict, "409 Conflict"),
+            (StatusCode::PreconditionFailed, "412 Precondition Failed"),
+            (StatusCode::PayloadTooLarge, "413 Payload Too Large"),
+            (StatusCode::UnsupportedMediaType, "415 Unsupported Media Type"),
+            (StatusCode::UnprocessableEntity, "422 Unprocessable Entity"),
+            (StatusCode::RequestHeaderFieldsTooLarge, "431 Request Header Fields Too Large"),
+            (StatusCode::InternalServerError, "500 Internal Server Error"),
+            (StatusCode::NotImplemented, "501 Not Implemented"),
+            (StatusCode::ServiceUnavailable, "503 Service Unavailable"),
+            (StatusCode::HttpVersionNotSupported, "505 HTTP Version Not Supported"),
+        ];
+
+        for (status, line) in cases {
+            let written = wire(&Response::new(status), false, true);
+            assert!(written.starts_with(&format!("HTTP/1.1 {}\r\n", line)), "{}", written);
+        }
+    }
+
+    #[test]
+    fn a_value_that_cannot_be_json_is_a_500() {
+        // JSON object keys must be strings.
+        let value: BTreeMap<Vec<u8>, i32> = [(vec![1], 1)].into_iter().collect();
+        let response = Response::json(StatusCode::Ok, &value);
+        assert_eq!(response.status, StatusCode::InternalServerError);
+        assert_eq!(response.body, b"JSON serialization error");
+    }
+}
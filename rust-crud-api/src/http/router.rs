@@ -0,0 +1,410 @@
+use crate::http::query::decode_segment;
+use crate::http::request::Method;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlash {
+    // `/users/` matches the same routes as `/users`.
+    Ignore,
+    // `/users/` is a different path and only matches if registered as such.
+    Strict,
+    // `/users/` is answered with a permanent redirect to `/users`.
+    Redirect,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PathParams {
+    params: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathParamError {
+    Missing(String),
+    Invalid { name: String, value: String },
+}
+
+impl fmt::Display for PathParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathParamError::Missing(name) => write!(f, "missing path parameter `{}`", name),
+            PathParamError::Invalid { name, value } => {
+                write!(f, "invalid value `{}` for path parameter `{}`", value, name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PathParamError {}
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<T, PathParamError> {
+        let value = self
+            .get(name)
+            .ok_or_else(|| PathParamError::Missing(name.to_string()))?;
+
+        value.parse().map_err(|_| PathParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+pub enum RouteMatch<'a, H> {
+    // The handler, its parameters and the pattern that matched, e.g. `/users/:id`.
+    Found(&'a H, PathParams, &'a str),
+    // The path exists but not for this method; carries the methods that would work.
+    MethodNotAllowed(Vec<Method>),
+    Redirect(String),
+    NotFound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    Param(String),
+}
+
+struct Route<H> {
+    method: Method,
+    segments: Vec<Segment>,
+    // `segments` written out again, for metrics and logs.
+    pattern: String,
+    handler: H,
+}
+
+/// Maps `(Method, pattern)` pairs to handlers.
+///
+/// Patterns are absolute paths whose segments are either literal or a
+/// `:name` parameter, e.g. `/users/:id`. Routes are tried in registration
+/// order. The router is generic over the handler type so the blocking and
+/// async servers can share it.
+pub struct Router<H> {
+    routes: Vec<Route<H>>,
+    trailing_slash: TrailingSlash,
+}
+
+impl<H> Default for Router<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H> Router<H> {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            trailing_slash: TrailingSlash::Ignore,
+        }
+    }
+
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    pub fn route(mut self, method: Method, pattern: &str, handler: H) -> Self {
+        let segments = parse_pattern(pattern);
+        self.routes.push(Route {
+            method,
+            pattern: format_pattern(&segments),
+            segments,
+            handler,
+        });
+        self
+    }
+
+    /// Mounts every route of `router` under `prefix`, e.g. `/api/v1`.
+    pub fn nest(mut self, prefix: &str, router: Router<H>) -> Self {
+        let prefix = parse_pattern(prefix);
+        for route in router.routes {
+            let mut segments = prefix.clone();
+            segments.extend(route.segments);
+            self.routes.push(Route {
+                method: route.method,
+                pattern: format_pattern(&segments),
+                segments,
+                handler: route.handler,
+            });
+        }
+        self
+    }
+
+    /// Each route's method and pattern, in registration order.
+    pub fn routes(&self) -> impl Iterator<Item = (Method, &str)> {
+        self.routes.iter().map(|route| (route.method, route.pattern.as_str()))
+    }
+
+    pub fn find(&self, method: Method, path: &str) -> RouteMatch<'_, H> {
+        let (segments, trailing) = split_path(path);
+
+        if trailing {
+            match self.trailing_slash {
+                TrailingSlash::Strict => return RouteMatch::NotFound,
+                TrailingSlash::Redirect => {
+                    return if self.routes.iter().any(|route| match_segments(&route.segments, &segments).is_some()) {
+                        RouteMatch::Redirect(path.trim_end_matches('/').to_string())
+                    } else {
+                        RouteMatch::NotFound
+                    };
+                }
+                TrailingSlash::Ignore => {}
+            }
+        }
+
+        let mut allowed = Vec::new();
+        let mut get_fallback = None;
+
+        for route in &self.routes {
+            let params = match match_segments(&route.segments, &segments) {
+                Some(params) => params,
+                None => continue,
+            };
+
+            if route.method == method {
+                return RouteMatch::Found(&route.handler, params, &route.pattern);
+            }
+            // HEAD is served by the GET handler unless registered explicitly.
+            if method == Method::Head && route.method == Method::Get && get_fallback.is_none() {
+                get_fallback = Some((&route.handler, params, &route.pattern));
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method);
+            }
+        }
+
+        if let Some((handler, params, pattern)) = get_fallback {
+            return RouteMatch::Found(handler, params, pattern);
+        }
+        if allowed.is_empty() {
+            return RouteMatch::NotFound;
+        }
+        if let Some(get) = allowed.iter().position(|allowed| *allowed == Method::Get) {
+            if !allowed.contains(&Method::Head) {
+                allowed.insert(get + 1, Method::Head);
+            }
+        }
+        RouteMatch::MethodNotAllowed(allowed)
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    pattern
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => Segment::Param(name.to_string()),
+            None => Segment::Static(segment.to_string()),
+        })
+        .collect()
+}
+
+fn format_pattern(segments: &[Segment]) -> String {
+    let segments: Vec<String> = segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::Static(literal) => literal.clone(),
+            Segment::Param(name) => format!(":{}", name),
+        })
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+// Splits a request path into its segments and whether it ended in a slash.
+// Empty segments in the middle (`/users//1`) are kept so they never match.
+fn split_path(path: &str) -> (Vec<&str>, bool) {
+    let trimmed = path.strip_prefix('/').unwrap_or(path);
+    if trimmed.is_empty() {
+        return (Vec::new(), false);
+    }
+
+    let trailing = trimmed.ends_with('/');
+    let trimmed = trimmed.strip_suffix('/').unwrap_or(trimmed);
+    (trimmed.split('/').collect(), trailing)
+}
+
+fn match_segments(pattern: &[Segment], segments: &[&str]) -> Option<PathParams> {
+    if pattern.len() != segments.len() {
+        return None;
+    }
+
+    let mut params = PathParams::default();
+    for (expected, actual) in pattern.iter().zip(segments) {
+        match expected {
+            Segment::Static(literal) if literal == actual => {}
+            // Literal segments compare as sent; parameters are decoded.
+            Segment::Param(name) if !actual.is_empty() => {
+                params.params.push((name.clone(), decode_segment(actual)));
+            }
+            _ => return None,
+        }
+    }
+    Some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Query, StatusCode};
+    use crate::server::{method_not_allowed, redirect};
+
+    // Handlers are just names here; `find` never calls them.
+    fn users() -> Router<&'static str> {
+        Router::new()
+            .route(Method::Post, "/", "create")
+            .route(Method::Get, "/", "list")
+            .route(Method::Get, "/:id", "get")
+            .route(Method::Put, "/:id", "update")
+            .route(Method::Delete, "/:id", "delete")
+            .route(Method::Post, "/:id/restore", "restore")
+            .route(Method::Post, "/purge", "purge")
+    }
+
+    fn router(trailing_slash: TrailingSlash) -> Router<&'static str> {
+        Router::new()
+            .trailing_slash(trailing_slash)
+            .nest("/users", users())
+            .route(Method::Get, "/", "root")
+            .route(Method::Get, "/healthz", "healthz")
+            .route(Method::Head, "/healthz", "healthz_head")
+    }
+
+    // The handler and pattern found, or how the lookup failed.
+    fn found(router: &Router<&'static str>, method: Method, path: &str) -> Result<(&'static str, String), String> {
+        match router.find(method, path) {
+            RouteMatch::Found(handler, _, pattern) => Ok((*handler, pattern.to_string())),
+            RouteMatch::MethodNotAllowed(allowed) => {
+                Err(format!("405 {}", allowed.iter().map(Method::as_str).collect::<Vec<_>>().join(", ")))
+            }
+            RouteMatch::Redirect(location) => Err(format!("308 {}", location)),
+            RouteMatch::NotFound => Err("404".to_string()),
+        }
+    }
+
+    #[test]
+    fn captures_path_parameters() {
+        let router = Router::new().route(Method::Get, "/users/:id/posts/:post", ());
+        let params = match router.find(Method::Get, "/users/%34%32/posts/hello%20there+%C3%A9%2F%zz") {
+            RouteMatch::Found(_, params, pattern) => {
+                assert_eq!(pattern, "/users/:id/posts/:post");
+                params
+            }
+            _ => panic!("no match"),
+        };
+
+        // Escapes are decoded, `+` is not a space in a path, and a malformed
+        // escape is kept as it was.
+        assert_eq!(params.get("id"), Some("42"));
+        assert_eq!(params.get("post"), Some("hello there+é/%zz"));
+        assert_eq!(params.parse::<i32>("id"), Ok(42));
+        assert_eq!(
+            params.parse::<i32>("post"),
+            Err(PathParamError::Invalid {
+                name: "post".to_string(),
+                value: "hello there+é/%zz".to_string(),
+            })
+        );
+        assert_eq!(params.parse::<i32>("nope"), Err(PathParamError::Missing("nope".to_string())));
+    }
+
+    #[test]
+    fn matches_routes_by_method_and_path() {
+        let router = router(TrailingSlash::Ignore);
+        let cases = [
+            (Method::Get, "/", Ok(("root", "/"))),
+            (Method::Get, "/users", Ok(("list", "/users"))),
+            (Method::Post, "/users", Ok(("create", "/users"))),
+            (Method::Get, "/users/7", Ok(("get", "/users/:id"))),
+            (Method::Get, "/users/%31", Ok(("get", "/users/:id"))),
+            (Method::Post, "/users/7/restore", Ok(("restore", "/users/:id/restore"))),
+            // `/:id` has no POST, so the literal route after it still gets a turn.
+            (Method::Post, "/users/purge", Ok(("purge", "/users/purge"))),
+            // Parameters take anything; the handler rejects ids that are not numbers.
+            (Method::Get, "/users/purge", Ok(("get", "/users/:id"))),
+            (Method::Get, "/users//7", Err("404")),
+            (Method::Get, "/users/7/restore/x", Err("404")),
+            (Method::Get, "/nowhere", Err("404")),
+            (Method::Get, "/Users", Err("404")),
+        ];
+
+        for (method, path, expected) in cases {
+            let expected = expected.map(|(handler, pattern)| (handler, pattern.to_string())).map_err(str::to_string);
+            assert_eq!(found(&router, method, path), expected, "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn answers_405_with_the_methods_the_path_allows() {
+        let router = router(TrailingSlash::Ignore);
+        assert_eq!(found(&router, Method::Delete, "/users"), Err("405 POST, GET, HEAD".to_string()));
+        assert_eq!(found(&router, Method::Patch, "/users/7"), Err("405 GET, HEAD, PUT, DELETE".to_string()));
+        // HEAD is listed once when it has a route of its own.
+        assert_eq!(found(&router, Method::Post, "/healthz"), Err("405 GET, HEAD".to_string()));
+        // `/users/purge` matches both `/:id` and `/purge`, so both sets of methods are allowed.
+        assert_eq!(found(&router, Method::Patch, "/users/purge"), Err("405 GET, HEAD, PUT, DELETE, POST".to_string()));
+
+        let response = method_not_allowed(&[Method::Post, Method::Get, Method::Head]);
+        assert_eq!(response.status, StatusCode::MethodNotAllowed);
+        assert_eq!(response.headers.get("Allow"), Some("POST, GET, HEAD"));
+    }
+
+    #[test]
+    fn head_falls_back_to_get() {
+        let router = router(TrailingSlash::Ignore);
+        assert_eq!(found(&router, Method::Head, "/users/7"), Ok(("get", "/users/:id".to_string())));
+        assert_eq!(found(&router, Method::Head, "/healthz"), Ok(("healthz_head", "/healthz".to_string())));
+        assert_eq!(found(&router, Method::Head, "/users/7/restore"), Err("405 POST".to_string()));
+    }
+
+    #[test]
+    fn applies_the_trailing_slash_policy() {
+        let ignore = router(TrailingSlash::Ignore);
+        assert_eq!(found(&ignore, Method::Get, "/users/"), Ok(("list", "/users".to_string())));
+        assert_eq!(found(&ignore, Method::Get, "/users/7/"), Ok(("get", "/users/:id".to_string())));
+
+        let strict = router(TrailingSlash::Strict);
+        assert_eq!(found(&strict, Method::Get, "/users/"), Err("404".to_string()));
+        assert_eq!(found(&strict, Method::Get, "/users"), Ok(("list", "/users".to_string())));
+
+        let redirect_policy = router(TrailingSlash::Redirect);
+        assert_eq!(found(&redirect_policy, Method::Get, "/users/7/"), Err("308 /users/7".to_string()));
+        // Only paths that exist without the slash are redirected.
+        assert_eq!(found(&redirect_policy, Method::Get, "/nowhere/"), Err("404".to_string()));
+
+        // The root path has no trailing slash to speak of.
+        for policy in [TrailingSlash::Ignore, TrailingSlash::Strict, TrailingSlash::Redirect] {
+            assert_eq!(found(&router(policy), Method::Get, "/"), Ok(("root", "/".to_string())));
+        }
+
+        let response = redirect("/users", &Query::parse("limit=5&sort=-id"));
+        assert_eq!(response.status, StatusCode::PermanentRedirect);
+        assert_eq!(response.headers.get("Location"), Some("/users?limit=5&sort=-id"));
+    }
+
+    #[test]
+    fn nest_mounts_routes_under_a_prefix() {
+        let router = Router::new().nest("/api/v1", Router::new().nest("/users/", users()));
+        let patterns: Vec<(Method, &str)> = router.routes().collect();
+        assert_eq!(
+            patterns,
+            [
+                (Method::Post, "/api/v1/users"),
+                (Method::Get, "/api/v1/users"),
+                (Method::Get, "/api/v1/users/:id"),
+                (Method::Put, "/api/v1/users/:id"),
+                (Method::Delete, "/api/v1/users/:id"),
+                (Method::Post, "/api/v1/users/:id/restore"),
+                (Method::Post, "/api/v1/users/purge"),
+            ]
+        );
+        assert_eq!(found(&router, Method::Get, "/api/v1/users/3"), Ok(("get", "/api/v1/users/:id".to_string())));
+        assert_eq!(found(&router, Method::Get, "/users/3"), Err("404".to_string()));
+    }
+}
@@ -0,0 +1,242 @@
+use crate::config::logging_config::{LogFormat, LogLevel};
+use crate::config::LoggingConfig;
+use chrono::{SecondsFormat, Utc};
+use serde_json::Value;
+use std::error::Error;
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+struct Logger {
+    level: LogLevel,
+    format: LogFormat,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Sets the level and format for the rest of the process. Anything logged
+/// before this, or if it is never called, goes out at info in logfmt.
+pub fn init(config: &LoggingConfig) {
+    let _ = LOGGER.set(Logger {
+        level: config.level,
+        format: config.format,
+    });
+}
+
+fn logger() -> &'static Logger {
+    LOGGER.get_or_init(|| Logger {
+        level: LogLevel::Info,
+        format: LogFormat::Logfmt,
+    })
+}
+
+pub fn enabled(level: LogLevel) -> bool {
+    level <= logger().level
+}
+
+/// Writes one line to stderr: the time, level and message, then `fields`
+/// in the order given. Use the macros below rather than calling this.
+pub fn write(level: LogLevel, message: &str, fields: &[(&str, Value)]) {
+    let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    let line = format_line(logger().format, &timestamp, level, message, fields);
+
+    // One write per line keeps lines from different threads whole.
+    let _ = io::stderr().lock().write_all(line.as_bytes());
+}
+
+fn format_line(format: LogFormat, timestamp: &str, level: LogLevel, message: &str, fields: &[(&str, Value)]) -> String {
+    let header = [
+        ("ts", Value::from(timestamp)),
+        ("level", Value::from(level.as_str())),
+        ("msg", Value::from(message)),
+    ];
+    let pairs = header.iter().chain(fields).map(|(key, value)| (*key, value));
+
+    let mut line = match format {
+        LogFormat::Json => {
+            let members: Vec<String> = pairs
+                .map(|(key, value)| format!("{}:{}", Value::from(key), value))
+                .collect();
+            format!("{{{}}}", members.join(","))
+        }
+        LogFormat::Logfmt => pairs
+            .map(|(key, value)| format!("{}={}", key, logfmt_value(value)))
+            .collect::<Vec<_>>()
+            .join(" "),
+    };
+    line.push('\n');
+    line
+}
+
+// Bare when unambiguous, otherwise quoted with JSON escapes.
+fn logfmt_value(value: &Value) -> String {
+    match value {
+        Value::String(text) => {
+            let bare = !text.is_empty()
+                && text.chars().all(|c| c.is_ascii_graphic() && c != '"' && c != '=' && c != '\\');
+            if bare {
+                text.clone()
+            } else {
+                value.to_string()
+            }
+        }
+        Value::Null => String::new(),
+        Value::Bool(_) | Value::Number(_) => value.to_string(),
+        Value::Array(_) | Value::Object(_) => Value::from(value.to_string()).to_string(),
+    }
+}
+
+/// `error` and its sources as one message, outermost first. A source whose
+/// text its wrapper already repeats is left out.
+pub fn error_chain(error: &(dyn Error + 'static)) -> String {
+    let mut chain = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        let message = error.to_string();
+        if !chain.contains(&message) {
+            chain.push_str(": ");
+            chain.push_str(&message);
+        }
+        source = error.source();
+    }
+    chain
+}
+
+/// `logging::log!(level, "message", key = value, ...)`: values are anything
+/// that implements `Serialize`, and are only evaluated if `level` is enabled.
+macro_rules! log {
+    ($level:expr, $message:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        if $crate::logging::enabled($level) {
+            $crate::logging::write(
+                $level,
+                &$message,
+                &[$((stringify!($key), ::serde_json::to_value(&$value).unwrap_or_default())),*],
+            );
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::logging::log!($crate::config::logging_config::LogLevel::Error, $($arg)+) };
+}
+
+// Named apart from the built-in `warn` attribute, which a plain `use` would clash with.
+macro_rules! warn_ {
+    ($($arg:tt)+) => { $crate::logging::log!($crate::config::logging_config::LogLevel::Warn, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::logging::log!($crate::config::logging_config::LogLevel::Info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::logging::log!($crate::config::logging_config::LogLevel::Debug, $($arg)+) };
+}
+
+pub(crate) use {debug, error, info, log, warn_ as warn};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fmt;
+
+    #[test]
+    fn logfmt_values_are_quoted_only_when_they_would_be_ambiguous() {
+        let cases = [
+            (json!("plain/path-1.0"), "plain/path-1.0"),
+            (json!(""), r#""""#),
+            (json!("two words"), r#""two words""#),
+            (json!(r#"say "hi""#), r#""say \"hi\"""#),
+            (json!("key=value"), r#""key=value""#),
+            (json!("first\nsecond"), r#""first\nsecond""#),
+            (json!(r"C:\temp"), r#""C:\\temp""#),
+            (json!("café"), r#""café""#),
+            (json!(null), ""),
+            (json!(true), "true"),
+            (json!(-1.5), "-1.5"),
+            (json!([1, "a"]), r#""[1,\"a\"]""#),
+            (json!({ "id": 1 }), r#""{\"id\":1}""#),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(logfmt_value(&value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn logfmt_lines_put_the_header_before_the_fields() {
+        let line = format_line(
+            LogFormat::Logfmt,
+            "2024-05-01T12:00:00.000Z",
+            LogLevel::Warn,
+            "request failed",
+            &[("status", json!(503)), ("path", json!("/users")), ("error", json!("timed out"))],
+        );
+        assert_eq!(
+            line,
+            "ts=2024-05-01T12:00:00.000Z level=warn msg=\"request failed\" status=503 path=/users error=\"timed out\"\n"
+        );
+    }
+
+    #[test]
+    fn json_lines_are_one_object_with_the_fields_in_order() {
+        let line = format_line(
+            LogFormat::Json,
+            "2024-05-01T12:00:00.000Z",
+            LogLevel::Info,
+            "user \"created\"",
+            &[("id", json!(42)), ("email", json!(null)), ("tags", json!(["a"]))],
+        );
+        assert_eq!(
+            line,
+            concat!(
+                r#"{"ts":"2024-05-01T12:00:00.000Z","level":"info","msg":"user \"created\"","#,
+                r#""id":42,"email":null,"tags":["a"]}"#,
+                "\n"
+            )
+        );
+        assert!(serde_json::from_str::<Value>(&line).is_ok());
+    }
+
+    #[derive(Debug)]
+    struct Failure {
+        message: &'static str,
+        source: Option<Box<Failure>>,
+    }
+
+    impl fmt::Display for Failure {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.message)
+        }
+    }
+
+    impl Error for Failure {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            self.source.as_deref().map(|source| source as &(dyn Error + 'static))
+        }
+    }
+
+    // `messages[0]` caused by `messages[1]`, and so on.
+    fn chain(messages: &[&'static str]) -> Failure {
+        let mut failure = None;
+        for message in messages.iter().rev() {
+            failure = Some(Failure {
+                message,
+                source: failure.map(Box::new),
+            });
+        }
+        failure.unwrap()
+    }
+
+    #[test]
+    fn error_chain_joins_the_sources_and_skips_repeated_ones() {
+        assert_eq!(error_chain(&chain(&["query failed"])), "query failed");
+        assert_eq!(
+            error_chain(&chain(&["query failed", "connection lost", "broken pipe"])),
+            "query failed: connection lost: broken pipe"
+        );
+        assert_eq!(
+            error_chain(&chain(&["db error: connection lost", "connection lost", "broken pipe"])),
+            "db error: connection lost: broken pipe"
+        );
+    }
+}
@@ -1,56 +1,290 @@
+
+mod config;
+mod http;
+mod logging;
+mod metrics;
+mod openapi;
 mod models;
 mod database;
+mod migrations;
+mod purge;
 mod repositories;
 mod services;
 mod controllers;
 mod utils;
+mod validation;
+mod routes;
 mod server;
+mod shutdown;
+// SQLite is blocking-only; the async build recognises its URLs to reject them.
+#[cfg(all(feature = "sqlite", not(feature = "async")))]
+mod sqlite_database;
+#[cfg(not(feature = "async"))]
+mod thread_pool;
+#[cfg(feature = "async")]
+mod async_server;
 
-use database::Database;
-use repositories::UserRepository;
+use config::AppConfig;
+use database::Backend;
+use dotenv::dotenv;
+use migrations::Command;
+use std::env;
+use std::process::ExitCode;
+#[cfg(not(feature = "async"))]
+use config::DatabaseConfig;
+#[cfg(not(feature = "async"))]
+use database::{Database, DatabaseError};
+#[cfg(not(feature = "async"))]
+use repositories::{AsyncUserRepository, MeteredUserRepository, PostgresUserRepository};
+#[cfg(all(feature = "sqlite", not(feature = "async")))]
+use repositories::SqliteUserRepository;
+#[cfg(all(feature = "sqlite", not(feature = "async")))]
+use sqlite_database::SqliteDatabase;
+#[cfg(not(feature = "async"))]
 use services::UserService;
+#[cfg(not(feature = "async"))]
 use server::Server;
-use dotenv::dotenv;
-use std::sync::{Arc, Mutex};
+#[cfg(not(feature = "async"))]
+use std::sync::Arc;
 
-fn main() {
+#[cfg(not(feature = "async"))]
+fn main() -> ExitCode {
     // Load environment variables from .env file (optional for Docker)
     dotenv().ok();
-    
-    // Initialize database
-    let mut db = match Database::new() {
-        Ok(db) => db,
-        Err(e) => {
-            eprintln!("Failed to connect to database: {}", e);
-            return;
-        }
+
+    // Defaults, config file, environment and flags, in that order
+    let (config, args) = match load_config() {
+        Ok(loaded) => loaded,
+        Err(status) => return status,
     };
+    logging::init(&config.logging);
 
-    // Setup database tables
-    if let Err(e) = db.setup_tables() {
-        eprintln!("Failed to setup database tables: {}", e);
-        return;
+    // `migrate ...` manages the schema and exits without serving
+    if let Some(status) = migrate_command(&args, &config) {
+        return status;
     }
 
-    // Initialize repository
-    let user_repository = UserRepository::new(db);
+    // Apply pending schema migrations before taking traffic; SQLite sets up its schema when opened
+    if matches!(config.database.backend, Backend::Postgres) {
+        if let Err(e) = migrations::run(Command::Up, false, &config.database) {
+            logging::error!("failed to migrate database", error = logging::error_chain(&e));
+            return ExitCode::FAILURE;
+        }
+    }
+
+    // Initialize the connection pool and the repository on top of it
+    let user_repository = match open_user_repository(&config.database) {
+        Ok(repository) => repository,
+        Err(e) => {
+            logging::error!("failed to connect to database", error = logging::error_chain(&e));
+            return ExitCode::FAILURE;
+        }
+    };
 
     // Initialize service
     let user_service = UserService::new(user_repository);
 
-    // Wrap service in Arc<Mutex<>> for thread safety
-    let user_service = Arc::new(Mutex::new(user_service));
+    // Share service between worker threads; each call borrows its own pooled connection.
+    // Main keeps its own handle so the pool is closed on exit.
+    let user_service = Arc::new(user_service);
+
+    // Hard-delete users whose retention period has run out
+    if let Some(interval) = config.admin.purge_interval {
+        if let Err(e) = purge::spawn(&user_service, config.admin.retention_days, interval) {
+            logging::error!("failed to start the purge job", error = e.to_string());
+            return ExitCode::FAILURE;
+        }
+    }
 
     // Create and run server
-    let server = match Server::new("0.0.0.0:8080", user_service) {
+    let server = match Server::new(config.server, config.admin, Arc::clone(&user_service)) {
+        Ok(server) => server,
+        Err(e) => {
+            logging::error!("failed to create server", error = e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Blocks until SIGTERM/SIGINT and the in-flight requests have drained
+    let status = match server.run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            logging::error!("server error", error = e.to_string());
+            ExitCode::FAILURE
+        }
+    };
+
+    // Dropping the last pool handle closes the idle connections
+    drop(user_service);
+    logging::info!("database connections closed");
+
+    status
+}
+
+// Opens the store `backend` names, behind the repository the service uses.
+// Every call is timed for the metrics.
+#[cfg(not(feature = "async"))]
+fn open_user_repository(config: &DatabaseConfig) -> Result<Box<dyn AsyncUserRepository>, DatabaseError> {
+    let repository: Box<dyn AsyncUserRepository> = match &config.backend {
+        Backend::Postgres => Box::new(PostgresUserRepository::new(Database::new(config)?)),
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite(path) => Box::new(SqliteUserRepository::new(SqliteDatabase::open(path, &config.pool)?)),
+    };
+    Ok(Box::new(MeteredUserRepository::new(repository)))
+}
+
+#[cfg(feature = "async")]
+fn main() -> ExitCode {
+    // Load environment variables from .env file (optional for Docker)
+    dotenv().ok();
+
+    // Defaults, config file, environment and flags, in that order
+    let (config, args) = match load_config() {
+        Ok(loaded) => loaded,
+        Err(status) => return status,
+    };
+    logging::init(&config.logging);
+
+    // `migrate ...` manages the schema and exits without serving
+    if let Some(status) = migrate_command(&args, &config) {
+        return status;
+    }
+
+    // The async stack only speaks Postgres
+    #[cfg(feature = "sqlite")]
+    if let Backend::Sqlite(path) = &config.database.backend {
+        logging::error!("SQLite is not supported by the async server; build without the `async` feature", path = path);
+        return ExitCode::FAILURE;
+    }
+
+    // Migrations use the blocking client, so they run before the runtime starts
+    if let Err(e) = migrations::run(Command::Up, false, &config.database) {
+        logging::error!("failed to migrate database", error = logging::error_chain(&e));
+        return ExitCode::FAILURE;
+    }
+
+    // worker_threads sizes the tokio runtime
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.server.worker_threads)
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            logging::error!("failed to start async runtime", error = e.to_string());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    runtime.block_on(run_async(config))
+}
+
+#[cfg(feature = "async")]
+async fn run_async(config: AppConfig) -> ExitCode {
+    use async_server::AsyncServer;
+    use database::AsyncDatabase;
+    use repositories::{AsyncPostgresUserRepository, MeteredUserRepository};
+    use services::UserService;
+    use std::sync::Arc;
+
+    // Initialize database connection pool
+    let db = match AsyncDatabase::connect(&config.database).await {
+        Ok(db) => db,
+        Err(e) => {
+            logging::error!("failed to connect to database", error = logging::error_chain(&e));
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // The service needs no lock: each call borrows its own pooled connection.
+    // Every call is timed for the metrics, as on the blocking stack.
+    let user_repository = AsyncPostgresUserRepository::new(db.clone());
+    let user_service = Arc::new(UserService::new(Box::new(MeteredUserRepository::new(Box::new(user_repository)))));
+
+    // Hard-delete users whose retention period has run out
+    if let Some(interval) = config.admin.purge_interval {
+        purge::spawn_async(&user_service, config.admin.retention_days, interval);
+    }
+
+    let server = match AsyncServer::new(config.server, config.admin, user_service).await {
         Ok(server) => server,
         Err(e) => {
-            eprintln!("Failed to create server: {}", e);
-            return;
+            logging::error!("failed to create server", error = e.to_string());
+            return ExitCode::FAILURE;
         }
     };
 
-    if let Err(e) = server.run() {
-        eprintln!("Server error: {}", e);
+    // Runs until SIGTERM/SIGINT and the in-flight requests have drained
+    let status = match server.run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            logging::error!("server error", error = e.to_string());
+            ExitCode::FAILURE
+        }
+    };
+
+    // Dropping the last pool handle closes the idle connections
+    drop(db);
+    logging::info!("database connections closed");
+
+    status
+}
+
+// Loads the configuration and returns it with the arguments that were not
+// settings. Err is the status to exit with: the configuration was invalid,
+// or `--print-config` was given and has been handled.
+fn load_config() -> Result<(AppConfig, Vec<String>), ExitCode> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (config, command_line) = match AppConfig::load(&args) {
+        Ok(loaded) => loaded,
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for error in errors {
+                eprintln!("  {}", error);
+            }
+            return Err(ExitCode::FAILURE);
+        }
+    };
+
+    if command_line.print_config {
+        print!("{}", config.to_toml());
+        return Err(ExitCode::SUCCESS);
+    }
+    if let Some(arg) = command_line.args.first().filter(|arg| *arg != "migrate") {
+        eprintln!("unexpected argument `{}`\n{}", arg, USAGE);
+        return Err(ExitCode::FAILURE);
+    }
+
+    Ok((config, command_line.args))
+}
+
+const USAGE: &str = "usage: rust-crud-api [--config FILE] [--print-config] [--SECTION.KEY VALUE ...] [migrate ...]";
+
+// Handles `rust-crud-api migrate [up | down [STEPS] | status] [--dry-run]`.
+// Returns None when the binary was started to serve requests.
+fn migrate_command(args: &[String], config: &AppConfig) -> Option<ExitCode> {
+    if args.first().map(String::as_str) != Some("migrate") {
+        return None;
+    }
+
+    if !matches!(config.database.backend, Backend::Postgres) {
+        logging::error!("migrate manages Postgres schemas; SQLite databases are set up when the server starts");
+        return Some(ExitCode::FAILURE);
     }
+
+    let (command, dry_run) = match migrations::parse_args(&args[1..]) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Some(ExitCode::FAILURE);
+        }
+    };
+
+    Some(match migrations::run(command, dry_run, &config.database) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            logging::error!("migration failed", error = logging::error_chain(&e));
+            ExitCode::FAILURE
+        }
+    })
 }
@@ -0,0 +1,348 @@
+use crate::database::PoolState;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// Upper bounds in seconds, shared by the request and query histograms.
+const BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+// Linux reports CPU time in clock ticks, which are 1/100 s on every
+// platform it runs on in practice.
+const TICKS_PER_SECOND: f64 = 100.0;
+
+#[derive(Clone, Default)]
+struct Histogram {
+    // Observations per bucket, not yet cumulative; the last one is +Inf.
+    counts: [u64; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        let bucket = BUCKETS.iter().position(|bound| value <= *bound).unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+    }
+
+    fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+// (method, route, status)
+type RequestKey = (String, String, u16);
+
+#[derive(Default)]
+struct Query {
+    durations: Histogram,
+    errors: u64,
+}
+
+/// Counters for one process; there is a single instance behind the free
+/// functions below, like the default registry of a Prometheus client.
+struct Metrics {
+    requests: Mutex<BTreeMap<RequestKey, Histogram>>,
+    // Keyed by repository method.
+    queries: Mutex<BTreeMap<&'static str, Query>>,
+    connections: AtomicI64,
+}
+
+static METRICS: Metrics = Metrics::new();
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            requests: Mutex::new(BTreeMap::new()),
+            queries: Mutex::new(BTreeMap::new()),
+            connections: AtomicI64::new(0),
+        }
+    }
+
+    fn record_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        lock(&self.requests)
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    fn record_query(&self, method: &'static str, elapsed: Duration, succeeded: bool) {
+        let mut queries = lock(&self.queries);
+        let query = queries.entry(method).or_default();
+        query.durations.observe(elapsed.as_secs_f64());
+        if !succeeded {
+            query.errors += 1;
+        }
+    }
+
+    fn write(&self, output: &mut String, pool: Option<PoolState>) {
+        let requests = lock(&self.requests).clone();
+        header(output, "http_requests_total", "counter", "Requests answered, by method, route and status.");
+        for ((method, route, status), histogram) in &requests {
+            let labels = labels(&[("method", method), ("route", route), ("status", &status.to_string())]);
+            let _ = writeln!(output, "http_requests_total{{{}}} {}", labels, histogram.count());
+        }
+        header(
+            output,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time from reading a request to writing its response.",
+        );
+        for ((method, route, status), histogram) in &requests {
+            let labels = labels(&[("method", method), ("route", route), ("status", &status.to_string())]);
+            write_histogram(output, "http_request_duration_seconds", &labels, histogram);
+        }
+
+        header(output, "http_connections_in_flight", "gauge", "Client connections currently open.");
+        let _ = writeln!(output, "http_connections_in_flight {}", self.connections.load(Ordering::Relaxed));
+
+        let queries: Vec<(&'static str, Histogram, u64)> = lock(&self.queries)
+            .iter()
+            .map(|(method, query)| (*method, query.durations.clone(), query.errors))
+            .collect();
+        header(output, "db_query_duration_seconds", "histogram", "Time spent in each repository method.");
+        for (method, histogram, _) in &queries {
+            write_histogram(output, "db_query_duration_seconds", &labels(&[("method", method)]), histogram);
+        }
+        header(output, "db_query_errors_total", "counter", "Repository calls that returned an error.");
+        for (method, _, errors) in &queries {
+            let _ = writeln!(output, "db_query_errors_total{{{}}} {}", labels(&[("method", method)]), errors);
+        }
+
+        if let Some(pool) = pool {
+            header(output, "db_pool_connections", "gauge", "Open database connections, by state.");
+            let _ = writeln!(output, "db_pool_connections{{state=\"idle\"}} {}", pool.idle);
+            let in_use = pool.connections.saturating_sub(pool.idle);
+            let _ = writeln!(output, "db_pool_connections{{state=\"in_use\"}} {}", in_use);
+            header(output, "db_pool_max_connections", "gauge", "Most connections the pool will open.");
+            let _ = writeln!(output, "db_pool_max_connections {}", pool.max_size);
+        }
+    }
+}
+
+// A panic while holding one of these cannot leave a half-made update behind
+// that matters, so a poisoned lock is still used.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Records an answered request. `route` is the matched pattern, so that ids
+/// in paths do not each get their own series.
+pub fn record_request(method: &str, route: &str, status: u16, elapsed: Duration) {
+    METRICS.record_request(method, route, status, elapsed);
+}
+
+pub fn record_query(method: &'static str, elapsed: Duration, succeeded: bool) {
+    METRICS.record_query(method, elapsed, succeeded);
+}
+
+/// Counts a client connection as open until the guard is dropped.
+pub struct ConnectionGuard(());
+
+pub fn connection_opened() -> ConnectionGuard {
+    METRICS.connections.fetch_add(1, Ordering::Relaxed);
+    ConnectionGuard(())
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        METRICS.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Everything recorded so far, plus `pool` and the process's own figures,
+/// in the Prometheus text exposition format.
+pub fn render(pool: Option<PoolState>) -> String {
+    let mut output = String::new();
+    METRICS.write(&mut output, pool);
+    write_process_metrics(&mut output);
+    output
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn write_histogram(output: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    let mut cumulative = 0;
+    for (bound, count) in BUCKETS.iter().map(|bound| bound.to_string()).chain(["+Inf".to_string()]).zip(histogram.counts) {
+        cumulative += count;
+        let _ = writeln!(output, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulative);
+    }
+    let _ = writeln!(output, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+    let _ = writeln!(output, "{}_count{{{}}} {}", name, labels, cumulative);
+}
+
+// The standard `process_*` metrics, read from /proc. Left out where that
+// is not available.
+fn write_process_metrics(output: &mut String) {
+    // Fields after the command name, which is in parentheses and may itself
+    // contain spaces; utime is field 14 of the whole line.
+    let stat = fs::read_to_string("/proc/self/stat").unwrap_or_default();
+    let fields: Vec<&str> = stat.rsplit_once(')').map(|(_, rest)| rest.split_whitespace().collect()).unwrap_or_default();
+    let field = |number: usize| fields.get(number - 3).and_then(|value| value.parse::<f64>().ok());
+
+    if let (Some(user), Some(system)) = (field(14), field(15)) {
+        header(output, "process_cpu_seconds_total", "counter", "User and system CPU time spent.");
+        let _ = writeln!(output, "process_cpu_seconds_total {}", (user + system) / TICKS_PER_SECOND);
+    }
+
+    let boot_time = fs::read_to_string("/proc/stat").ok().and_then(|stat| {
+        stat.lines()
+            .find_map(|line| line.strip_prefix("btime "))
+            .and_then(|seconds| seconds.trim().parse::<f64>().ok())
+    });
+    if let (Some(boot_time), Some(started)) = (boot_time, field(22)) {
+        header(output, "process_start_time_seconds", "gauge", "Start time of the process since the Unix epoch.");
+        let _ = writeln!(output, "process_start_time_seconds {}", boot_time + started / TICKS_PER_SECOND);
+    }
+
+    let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+    let status_value = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(|value| value.parse::<u64>().ok())
+    };
+    if let Some(kilobytes) = status_value("VmRSS:") {
+        header(output, "process_resident_memory_bytes", "gauge", "Resident memory size in bytes.");
+        let _ = writeln!(output, "process_resident_memory_bytes {}", kilobytes * 1024);
+    }
+    if let Some(kilobytes) = status_value("VmSize:") {
+        header(output, "process_virtual_memory_bytes", "gauge", "Virtual memory size in bytes.");
+        let _ = writeln!(output, "process_virtual_memory_bytes {}", kilobytes * 1024);
+    }
+    if let Some(threads) = status_value("Threads:") {
+        header(output, "process_threads", "gauge", "OS threads in the process.");
+        let _ = writeln!(output, "process_threads {}", threads);
+    }
+
+    if let Ok(entries) = fs::read_dir("/proc/self/fd") {
+        header(output, "process_open_fds", "gauge", "Open file descriptors.");
+        let _ = writeln!(output, "process_open_fds {}", entries.count());
+    }
+    let max_fds = fs::read_to_string("/proc/self/limits").ok().and_then(|limits| {
+        limits
+            .lines()
+            .find_map(|line| line.strip_prefix("Max open files"))
+            .and_then(|limit| limit.split_whitespace().next())
+            .and_then(|soft| soft.parse::<u64>().ok())
+    });
+    if let Some(max_fds) = max_fds {
+        header(output, "process_max_fds", "gauge", "Limit on open file descriptors.");
+        let _ = writeln!(output, "process_max_fds {}", max_fds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_writes_the_text_exposition_format() {
+        let metrics = Metrics::new();
+        for millis in [500, 2000, 10_000] {
+            metrics.record_request("GET", "/users", 200, Duration::from_millis(millis));
+        }
+        // On a bucket's bound, which counts as inside it.
+        metrics.record_request("POST", "/a\"b\\c\nd", 201, Duration::from_millis(1));
+        metrics.record_query("find_by_id", Duration::from_millis(250), true);
+        metrics.record_query("find_by_id", Duration::from_millis(500), false);
+
+        let mut output = String::new();
+        let pool = PoolState {
+            connections: 3,
+            idle: 1,
+            max_size: 16,
+        };
+        metrics.write(&mut output, Some(pool));
+
+        let expected = [
+                r#"# HELP http_requests_total Requests answered, by method, route and status."#,
+                r#"# TYPE http_requests_total counter"#,
+                r#"http_requests_total{method="GET",route="/users",status="200"} 3"#,
+                r#"http_requests_total{method="POST",route="/a\"b\\c\nd",status="201"} 1"#,
+                r#"# HELP http_request_duration_seconds Time from reading a request to writing its response."#,
+                r#"# TYPE http_request_duration_seconds histogram"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="0.001"} 0"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="0.0025"} 0"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="0.005"} 0"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="0.01"} 0"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="0.025"} 0"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="0.05"} 0"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="0.1"} 0"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="0.25"} 0"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="0.5"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="1"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="2.5"} 2"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="5"} 2"#,
+                r#"http_request_duration_seconds_bucket{method="GET",route="/users",status="200",le="+Inf"} 3"#,
+                r#"http_request_duration_seconds_sum{method="GET",route="/users",status="200"} 12.5"#,
+                r#"http_request_duration_seconds_count{method="GET",route="/users",status="200"} 3"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="0.001"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="0.0025"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="0.005"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="0.01"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="0.025"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="0.05"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="0.1"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="0.25"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="0.5"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="1"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="2.5"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="5"} 1"#,
+                r#"http_request_duration_seconds_bucket{method="POST",route="/a\"b\\c\nd",status="201",le="+Inf"} 1"#,
+                r#"http_request_duration_seconds_sum{method="POST",route="/a\"b\\c\nd",status="201"} 0.001"#,
+                r#"http_request_duration_seconds_count{method="POST",route="/a\"b\\c\nd",status="201"} 1"#,
+                r#"# HELP http_connections_in_flight Client connections currently open."#,
+                r#"# TYPE http_connections_in_flight gauge"#,
+                r#"http_connections_in_flight 0"#,
+                r#"# HELP db_query_duration_seconds Time spent in each repository method."#,
+                r#"# TYPE db_query_duration_seconds histogram"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="0.001"} 0"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="0.0025"} 0"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="0.005"} 0"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="0.01"} 0"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="0.025"} 0"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="0.05"} 0"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="0.1"} 0"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="0.25"} 1"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="0.5"} 2"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="1"} 2"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="2.5"} 2"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="5"} 2"#,
+                r#"db_query_duration_seconds_bucket{method="find_by_id",le="+Inf"} 2"#,
+                r#"db_query_duration_seconds_sum{method="find_by_id"} 0.75"#,
+                r#"db_query_duration_seconds_count{method="find_by_id"} 2"#,
+                r#"# HELP db_query_errors_total Repository calls that returned an error."#,
+                r#"# TYPE db_query_errors_total counter"#,
+                r#"db_query_errors_total{method="find_by_id"} 1"#,
+                r#"# HELP db_pool_connections Open database connections, by state."#,
+                r#"# TYPE db_pool_connections gauge"#,
+                r#"db_pool_connections{state="idle"} 1"#,
+                r#"db_pool_connections{state="in_use"} 2"#,
+                r#"# HELP db_pool_max_connections Most connections the pool will open."#,
+                r#"# TYPE db_pool_max_connections gauge"#,
+                r#"db_pool_max_connections 16"#,
+        ];
+        assert_eq!(output.lines().collect::<Vec<_>>(), expected);
+        assert!(output.ends_with('\n'));
+    }
+}
@@ -0,0 +1,460 @@
+use crate::config::DatabaseConfig;
+use crate::database::{self, DatabaseError};
+use crate::logging;
+use postgres::{Client, Error as PostgresError};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+macro_rules! migration {
+    ($version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $name, ".down.sql")),
+        }
+    };
+}
+
+// Applied in order. Never edit one that has shipped; add a new one instead,
+// the checksum check will refuse to start otherwise.
+const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_create_users"),
+    migration!(2, "0002_user_audit_columns"),
+    migration!(3, "0003_user_soft_delete"),
+];
+
+/// Lists the applied versions, for `pending` to compare with this build.
+pub const APPLIED_VERSIONS: &str = "SELECT version FROM schema_migrations";
+
+const CREATE_TRACKING_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version BIGINT PRIMARY KEY,
+        name VARCHAR NOT NULL,
+        checksum VARCHAR NOT NULL,
+        applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    )
+";
+
+// Key for the session-level advisory lock held while migrating, so replicas
+// that start together apply each migration exactly once.
+const LOCK_KEY: i64 = 0x7363_6865_6d61;
+
+const USAGE: &str = "usage: rust-crud-api migrate [up | down [STEPS] | status] [--dry-run]";
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(DatabaseError),
+    // An applied migration no longer matches the SQL embedded in this build.
+    ChecksumMismatch { version: i64, name: String },
+    // Rolling back a migration this build has no down SQL for.
+    Unknown { version: i64, name: String },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Database(e) => write!(f, "{}", e),
+            MigrationError::ChecksumMismatch { version, name } => {
+                write!(f, "migration {} ({}) was modified after it was applied", version, name)
+            }
+            MigrationError::Unknown { version, name } => {
+                write!(f, "migration {} ({}) is not part of this build", version, name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MigrationError::Database(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<DatabaseError> for MigrationError {
+    fn from(error: DatabaseError) -> Self {
+        MigrationError::Database(error)
+    }
+}
+
+impl From<PostgresError> for MigrationError {
+    fn from(error: PostgresError) -> Self {
+        MigrationError::Database(DatabaseError::from(error))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Up,
+    Down(usize),
+    Status,
+}
+
+/// Parses the arguments after `migrate`. Returns the command and whether
+/// `--dry-run` was given.
+pub fn parse_args(args: &[String]) -> Result<(Command, bool), String> {
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let args: Vec<&str> = args.iter().map(String::as_str).filter(|arg| *arg != "--dry-run").collect();
+
+    let command = match args.as_slice() {
+        [] | ["up"] => Command::Up,
+        ["down"] => Command::Down(1),
+        ["down", steps] => match steps.parse() {
+            Ok(steps) if steps > 0 => Command::Down(steps),
+            _ => return Err(format!("STEPS must be a positive number, got `{}`\n{}", steps, USAGE)),
+        },
+        ["status"] => Command::Status,
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok((command, dry_run))
+}
+
+/// Runs `command` on a dedicated connection outside the pool.
+pub fn run(command: Command, dry_run: bool, config: &DatabaseConfig) -> Result<(), MigrationError> {
+    let mut client = database::connect_single(config)?;
+    let mut migrator = Migrator {
+        client: &mut client,
+        dry_run,
+    };
+
+    match command {
+        Command::Up => {
+            let applied = migrator.locked(Migrator::up)?;
+            if applied == 0 {
+                logging::info!("database schema is up to date");
+            }
+        }
+        Command::Down(steps) => {
+            migrator.locked(|migrator| migrator.down(steps))?;
+        }
+        Command::Status => migrator.status()?,
+    }
+    Ok(())
+}
+
+/// How many of this build's migrations are not among `applied`.
+pub fn pending(applied: &[i64]) -> usize {
+    MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .count()
+}
+
+struct AppliedMigration {
+    version: i64,
+    name: String,
+    checksum: String,
+}
+
+struct Migrator<'a> {
+    client: &'a mut Client,
+    // Print the SQL that would run instead of running it.
+    dry_run: bool,
+}
+
+impl Migrator<'_> {
+    fn locked<T, F>(&mut self, action: F) -> Result<T, MigrationError>
+    where
+        F: FnOnce(&mut Self) -> Result<T, MigrationError>,
+    {
+        self.client.execute("SELECT pg_advisory_lock($1)", &[&LOCK_KEY])?;
+
+        let result = self.prepare().and_then(|()| action(self));
+
+        // Closing the connection would release the lock too, but do not rely on it.
+        self.client.execute("SELECT pg_advisory_unlock($1)", &[&LOCK_KEY])?;
+        result
+    }
+
+    fn prepare(&mut self) -> Result<(), MigrationError> {
+        if !self.dry_run {
+            self.client.batch_execute(CREATE_TRACKING_TABLE)?;
+        }
+        Ok(())
+    }
+
+    fn up(&mut self) -> Result<usize, MigrationError> {
+        let applied = self.applied()?;
+        verify(MIGRATIONS, &applied)?;
+
+        let pending = to_apply(MIGRATIONS, &applied);
+        for migration in &pending {
+            if self.dry_run {
+                println!("-- {} (up)\n{}\n", migration.name, migration.up.trim());
+                continue;
+            }
+
+            let mut transaction = self.client.transaction()?;
+            transaction.batch_execute(migration.up)?;
+            transaction.execute(
+                "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)",
+                &[&migration.version, &migration.name, &checksum(migration.up)],
+            )?;
+            transaction.commit()?;
+            logging::info!("applied migration", name = migration.name);
+        }
+
+        Ok(pending.len())
+    }
+
+    fn down(&mut self, steps: usize) -> Result<usize, MigrationError> {
+        let applied = self.applied()?;
+        verify(MIGRATIONS, &applied)?;
+
+        let mut reverted = 0;
+        for migration in to_revert(MIGRATIONS, &applied, steps)? {
+            if self.dry_run {
+                println!("-- {} (down)\n{}\n", migration.name, migration.down.trim());
+            } else {
+                let mut transaction = self.client.transaction()?;
+                transaction.batch_execute(migration.down)?;
+                transaction.execute("DELETE FROM schema_migrations WHERE version = $1", &[&migration.version])?;
+                transaction.commit()?;
+                logging::info!("reverted migration", name = migration.name);
+            }
+            reverted += 1;
+        }
+
+        Ok(reverted)
+    }
+
+    fn status(&mut self) -> Result<(), MigrationError> {
+        let applied = self.applied()?;
+
+        for migration in MIGRATIONS {
+            let state = match applied.iter().find(|done| done.version == migration.version) {
+                Some(done) if done.checksum != checksum(migration.up) => "modified",
+                Some(_) => "applied",
+                None => "pending",
+            };
+            println!("{:>8}  {}", state, migration.name);
+        }
+        for done in applied.iter().filter(|done| !MIGRATIONS.iter().any(|m| m.version == done.version)) {
+            println!("{:>8}  {}", "unknown", done.name);
+        }
+        Ok(())
+    }
+
+    // Applied migrations in version order; empty if the tracking table does
+    // not exist yet, which only a dry run or `status` can observe.
+    fn applied(&mut self) -> Result<Vec<AppliedMigration>, MigrationError> {
+        let exists: bool = self
+            .client
+            .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[])?
+            .get(0);
+        if !exists {
+            return Ok(Vec::new());
+        }
+
+        let rows = self
+            .client
+            .query("SELECT version, name, checksum FROM schema_migrations ORDER BY version", &[])?;
+        Ok(rows
+            .iter()
+            .map(|row| AppliedMigration {
+                version: row.get(0),
+                name: row.get(1),
+                checksum: row.get(2),
+            })
+            .collect())
+    }
+}
+
+// The migrations `up` applies, in order.
+fn to_apply<'m>(migrations: &'m [Migration], applied: &[AppliedMigration]) -> Vec<&'m Migration> {
+    migrations
+        .iter()
+        .filter(|migration| !applied.iter().any(|done| done.version == migration.version))
+        .collect()
+}
+
+// The last `steps` applied migrations, newest first. Fails before anything is
+// reverted if one of them is not part of this build.
+fn to_revert<'m>(
+    migrations: &'m [Migration],
+    applied: &[AppliedMigration],
+    steps: usize,
+) -> Result<Vec<&'m Migration>, MigrationError> {
+    applied
+        .iter()
+        .rev()
+        .take(steps)
+        .map(|done| {
+            migrations
+                .iter()
+                .find(|migration| migration.version == done.version)
+                .ok_or_else(|| MigrationError::Unknown {
+                    version: done.version,
+                    name: done.name.clone(),
+                })
+        })
+        .collect()
+}
+
+fn verify(migrations: &[Migration], applied: &[AppliedMigration]) -> Result<(), MigrationError> {
+    for done in applied {
+        match migrations.iter().find(|migration| migration.version == done.version) {
+            Some(migration) if checksum(migration.up) != done.checksum => {
+                return Err(MigrationError::ChecksumMismatch {
+                    version: done.version,
+                    name: done.name.clone(),
+                });
+            }
+            Some(_) => {}
+            // An older replica during a rolling deploy; the schema is ahead of it.
+            None => logging::warn!("database has a migration this build does not know about", name = done.name),
+        }
+    }
+    Ok(())
+}
+
+fn checksum(sql: &str) -> String {
+    format!("{:x}", Sha256::digest(sql.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            name: "0001_a",
+            up: "CREATE TABLE a ();",
+            down: "DROP TABLE a;",
+        },
+        Migration {
+            version: 2,
+            name: "0002_b",
+            up: "CREATE TABLE b ();",
+            down: "DROP TABLE b;",
+        },
+        Migration {
+            version: 3,
+            name: "0003_c",
+            up: "CREATE TABLE c ();",
+            down: "DROP TABLE c;",
+        },
+    ];
+
+    // Rows as `schema_migrations` would hold them after applying `versions`.
+    fn applied(versions: &[i64]) -> Vec<AppliedMigration> {
+        versions
+            .iter()
+            .map(|&version| {
+                let migration = TEST_MIGRATIONS.iter().find(|migration| migration.version == version);
+                AppliedMigration {
+                    version,
+                    name: migration.map_or(format!("{:04}_gone", version), |migration| migration.name.to_string()),
+                    checksum: migration.map_or(String::new(), |migration| checksum(migration.up)),
+                }
+            })
+            .collect()
+    }
+
+    fn names(migrations: &[&Migration]) -> Vec<&'static str> {
+        migrations.iter().map(|migration| migration.name).collect()
+    }
+
+    #[test]
+    fn parses_the_command_line() {
+        let cases = [
+            ("", Ok((Command::Up, false))),
+            ("up", Ok((Command::Up, false))),
+            ("--dry-run", Ok((Command::Up, true))),
+            ("up --dry-run", Ok((Command::Up, true))),
+            ("down", Ok((Command::Down(1), false))),
+            ("down 3", Ok((Command::Down(3), false))),
+            ("--dry-run down 2", Ok((Command::Down(2), true))),
+            ("status", Ok((Command::Status, false))),
+            ("down 0", Err("STEPS must be a positive number, got `0`")),
+            ("down -1", Err("STEPS must be a positive number, got `-1`")),
+            ("down two", Err("STEPS must be a positive number, got `two`")),
+            ("sideways", Err(USAGE)),
+            ("up 2", Err(USAGE)),
+            ("status --verbose", Err(USAGE)),
+        ];
+
+        for (line, expected) in cases {
+            let args: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+            let parsed = parse_args(&args).map_err(|e| e.lines().next().unwrap_or_default().to_string());
+            assert_eq!(parsed, expected.map_err(str::to_string), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn applies_what_is_missing_in_version_order() {
+        assert_eq!(names(&to_apply(TEST_MIGRATIONS, &applied(&[]))), ["0001_a", "0002_b", "0003_c"]);
+        assert_eq!(names(&to_apply(TEST_MIGRATIONS, &applied(&[1]))), ["0002_b", "0003_c"]);
+        // A gap left by a migration merged out of order is filled in.
+        assert_eq!(names(&to_apply(TEST_MIGRATIONS, &applied(&[1, 3]))), ["0002_b"]);
+        assert!(to_apply(TEST_MIGRATIONS, &applied(&[1, 2, 3])).is_empty());
+    }
+
+    #[test]
+    fn rolls_back_the_newest_first() {
+        let all = applied(&[1, 2, 3]);
+        assert_eq!(names(&to_revert(TEST_MIGRATIONS, &all, 1).unwrap()), ["0003_c"]);
+        assert_eq!(names(&to_revert(TEST_MIGRATIONS, &all, 2).unwrap()), ["0003_c", "0002_b"]);
+        assert_eq!(names(&to_revert(TEST_MIGRATIONS, &all, 10).unwrap()), ["0003_c", "0002_b", "0001_a"]);
+        assert!(to_revert(TEST_MIGRATIONS, &applied(&[]), 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_to_roll_back_a_migration_it_does_not_have() {
+        let applied = applied(&[1, 2, 3, 4]);
+        match to_revert(TEST_MIGRATIONS, &applied, 2) {
+            Err(MigrationError::Unknown { version, name }) => {
+                assert_eq!(version, 4);
+                assert_eq!(name, "0004_gone");
+            }
+            other => panic!("expected Unknown, got {:?}", other.map(|migrations| names(&migrations))),
+        }
+    }
+
+    #[test]
+    fn rejects_an_applied_migration_whose_sql_changed() {
+        let mut applied = applied(&[1, 2]);
+        assert!(verify(TEST_MIGRATIONS, &applied).is_ok());
+
+        applied[1].checksum = checksum("CREATE TABLE b (id INT);");
+        match verify(TEST_MIGRATIONS, &applied) {
+            Err(MigrationError::ChecksumMismatch { version, name }) => {
+                assert_eq!(version, 2);
+                assert_eq!(name, "0002_b");
+            }
+            other => panic!("expected ChecksumMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tolerates_migrations_from_a_newer_build() {
+        assert!(verify(TEST_MIGRATIONS, &applied(&[1, 2, 3, 4])).is_ok());
+    }
+
+    #[test]
+    fn checksums_are_sha256_of_the_up_sql() {
+        assert_eq!(checksum(""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_ne!(checksum("SELECT 1;"), checksum("SELECT 1; "));
+    }
+
+    #[test]
+    fn shipped_migrations_are_numbered_in_order() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i64 + 1);
+            assert!(migration.name.starts_with(&format!("{:04}_", migration.version)), "{}", migration.name);
+        }
+        assert_eq!(pending(&[]), MIGRATIONS.len());
+        assert_eq!(pending(&[1, 2]), MIGRATIONS.len() - 2);
+    }
+}
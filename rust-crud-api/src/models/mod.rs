@@ -1,3 +1,7 @@
 pub mod user;
+pub mod user_patch;
+pub mod user_query;
 
 pub use user::User;
+pub use user_patch::{UserChanges, UserPatch};
+pub use user_query::{UserPage, UserQuery};
@@ -1,18 +1,44 @@
+use crate::validation::{FieldError, Validate, Validator};
+use chrono::{DateTime, Utc};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+pub const MAX_NAME_LENGTH: usize = 100;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct User {
     pub id: Option<i32>,
     pub name: String,
     pub email: String,
+    // Maintained by the database; values sent by clients are ignored.
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    // Bumped on every write to the row.
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+    // Set while the user is soft-deleted; such users are only returned to
+    // admins who ask for them with `include_deleted=true`.
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
-impl User {
-    pub fn with_id(id: i32, name: String, email: String) -> Self {
-        Self {
-            id: Some(id),
-            name,
-            email,
-        }
+impl Validate for User {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut validator = Validator::new();
+        validator
+            .field("name", &self.name)
+            .required()
+            .length(1, MAX_NAME_LENGTH)
+            .pattern(no_control_characters(), "must not contain control characters");
+        validator.field("email", &self.email).required().email();
+        validator.finish()
     }
 }
+
+fn no_control_characters() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"^\P{Cc}*$").expect("valid regex"))
+}
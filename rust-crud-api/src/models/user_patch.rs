@@ -0,0 +1,265 @@
+use crate::models::User;
+use crate::validation::FieldError;
+use serde_json::Value;
+
+// Fields a patch may mention but must leave as they are.
+const READ_ONLY_FIELDS: [&str; 5] = ["id", "created_at", "updated_at", "version", "deleted_at"];
+
+/// Body of `PATCH /users/:id`; the Content-Type decides which kind it is.
+pub enum UserPatch {
+    // RFC 7396: members replace the current value, `null` removes it.
+    Merge(Value),
+    // RFC 6902: operations applied in order, all or nothing.
+    Json(json_patch::Patch),
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    // The patched document is not a valid user.
+    Invalid(Vec<FieldError>),
+    // A JSON Patch operation could not be applied, e.g. a failed `test`.
+    Failed(String),
+}
+
+impl UserPatch {
+    /// Applies the patch to the JSON form of `user` and reads the result
+    /// back. Field rules are not checked here, only the document's shape.
+    pub fn apply(&self, user: &User) -> Result<User, PatchError> {
+        let original = serde_json::to_value(user).expect("User always serializes");
+        let mut document = original.clone();
+
+        match self {
+            UserPatch::Merge(patch) => json_patch::merge(&mut document, patch),
+            UserPatch::Json(patch) => {
+                json_patch::patch(&mut document, patch).map_err(|e| PatchError::Failed(e.to_string()))?
+            }
+        }
+
+        let mut errors = Vec::new();
+        for field in READ_ONLY_FIELDS {
+            if document.get(field) != original.get(field) {
+                errors.push(FieldError {
+                    field,
+                    code: "read_only",
+                    message: format!("{} cannot be changed", field),
+                });
+            }
+        }
+        let name = string_field(&document, "name", &mut errors);
+        let email = string_field(&document, "email", &mut errors);
+
+        match (name, email) {
+            (Some(name), Some(email)) if errors.is_empty() => Ok(User {
+                name,
+                email,
+                ..user.clone()
+            }),
+            _ => Err(PatchError::Invalid(errors)),
+        }
+    }
+}
+
+/// Columns that differ between the stored and the patched user; only these
+/// are written back.
+#[derive(Debug, Default)]
+pub struct UserChanges {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+impl UserChanges {
+    pub fn between(current: &User, patched: &User) -> Self {
+        let changed = |old: &String, new: &String| (old != new).then(|| new.clone());
+        Self {
+            name: changed(&current.name, &patched.name),
+            email: changed(&current.email, &patched.email),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.email.is_none()
+    }
+}
+
+fn string_field(document: &Value, field: &'static str, errors: &mut Vec<FieldError>) -> Option<String> {
+    match document.get(field) {
+        Some(Value::String(value)) => Some(value.clone()),
+        None | Some(Value::Null) => {
+            errors.push(FieldError {
+                field,
+                code: "required",
+                message: format!("{} must not be empty", field),
+            });
+            None
+        }
+        Some(_) => {
+            errors.push(FieldError {
+                field,
+                code: "invalid_type",
+                message: format!("{} must be a string", field),
+            });
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::Version;
+    use crate::http::{Headers, Method, PathParams, Query, Request};
+    use crate::utils::{get_patch_from_request_body, PatchBodyError};
+    use serde_json::json;
+
+    fn user() -> User {
+        User {
+            id: Some(7),
+            name: "Ada".to_string(),
+            email: "ada@example.com".to_string(),
+            created_at: None,
+            updated_at: None,
+            version: Some(3),
+            deleted_at: None,
+        }
+    }
+
+    fn merge(patch: Value) -> UserPatch {
+        UserPatch::Merge(patch)
+    }
+
+    fn json_patch(operations: Value) -> UserPatch {
+        UserPatch::Json(serde_json::from_value(operations).unwrap())
+    }
+
+    // The patched name and email, or the failing fields and their codes.
+    fn outcome(patch: UserPatch) -> Result<(String, String), Vec<(&'static str, &'static str)>> {
+        match patch.apply(&user()) {
+            Ok(patched) => {
+                assert_eq!((patched.id, patched.version), (Some(7), Some(3)));
+                Ok((patched.name, patched.email))
+            }
+            Err(PatchError::Invalid(errors)) => Err(errors.iter().map(|error| (error.field, error.code)).collect()),
+            Err(PatchError::Failed(message)) => panic!("patch failed: {}", message),
+        }
+    }
+
+    fn changed(name: &str, email: &str) -> Result<(String, String), Vec<(&'static str, &'static str)>> {
+        Ok((name.to_string(), email.to_string()))
+    }
+
+    #[test]
+    fn applies_merge_patches() {
+        assert_eq!(outcome(merge(json!({ "name": "Grace" }))), changed("Grace", "ada@example.com"));
+        assert_eq!(outcome(merge(json!({}))), changed("Ada", "ada@example.com"));
+        // Members that are not user fields are ignored, like on PUT.
+        assert_eq!(outcome(merge(json!({ "nickname": "G" }))), changed("Ada", "ada@example.com"));
+        // Sending the current value of a read-only field is not a change.
+        assert_eq!(outcome(merge(json!({ "id": 7, "version": 3 }))), changed("Ada", "ada@example.com"));
+
+        assert_eq!(outcome(merge(json!({ "name": null }))), Err(vec![("name", "required")]));
+        assert_eq!(outcome(merge(json!({ "email": 5 }))), Err(vec![("email", "invalid_type")]));
+        assert_eq!(
+            outcome(merge(json!({ "id": 8, "version": null, "name": "Grace" }))),
+            Err(vec![("id", "read_only"), ("version", "read_only")])
+        );
+        assert_eq!(
+            outcome(merge(json!({ "created_at": "2024-01-01T00:00:00Z" }))),
+            Err(vec![("created_at", "read_only")])
+        );
+    }
+
+    #[test]
+    fn applies_json_patches() {
+        assert_eq!(
+            outcome(json_patch(json!([
+                { "op": "test", "path": "/name", "value": "Ada" },
+                { "op": "replace", "path": "/name", "value": "Grace" },
+                { "op": "copy", "from": "/email", "path": "/nickname" },
+            ]))),
+            changed("Grace", "ada@example.com")
+        );
+        assert_eq!(
+            outcome(json_patch(json!([{ "op": "move", "from": "/email", "path": "/name" }]))),
+            Err(vec![("email", "required")])
+        );
+        assert_eq!(
+            outcome(json_patch(json!([{ "op": "remove", "path": "/id" }]))),
+            Err(vec![("id", "read_only")])
+        );
+        assert_eq!(
+            outcome(json_patch(json!([{ "op": "add", "path": "/deleted_at", "value": "2024-01-01T00:00:00Z" }]))),
+            Err(vec![("deleted_at", "read_only")])
+        );
+    }
+
+    #[test]
+    fn json_patches_are_all_or_nothing() {
+        let patch = json_patch(json!([
+            { "op": "replace", "path": "/name", "value": "Grace" },
+            { "op": "test", "path": "/email", "value": "grace@example.com" },
+        ]));
+        assert!(matches!(patch.apply(&user()), Err(PatchError::Failed(_))));
+
+        let patch = json_patch(json!([{ "op": "replace", "path": "/missing/field", "value": 1 }]));
+        assert!(matches!(patch.apply(&user()), Err(PatchError::Failed(_))));
+    }
+
+    #[test]
+    fn changes_list_only_the_columns_that_differ() {
+        let current = user();
+        let mut patched = user();
+        let unchanged = UserChanges::between(&current, &patched);
+        assert!(unchanged.is_empty());
+
+        patched.email = "grace@example.com".to_string();
+        let changes = UserChanges::between(&current, &patched);
+        assert!(!changes.is_empty());
+        assert_eq!(changes.name, None);
+        assert_eq!(changes.email.as_deref(), Some("grace@example.com"));
+    }
+
+    fn patch_request(content_type: Option<&str>, body: &str) -> Request {
+        let mut headers = Headers::new();
+        if let Some(content_type) = content_type {
+            headers.append("Content-Type".to_string(), content_type.to_string());
+        }
+        Request {
+            method: Method::Patch,
+            path: "/users/7".to_string(),
+            query: Query::default(),
+            version: Version::Http11,
+            headers,
+            body: body.as_bytes().to_vec(),
+            params: PathParams::default(),
+        }
+    }
+
+    #[test]
+    fn the_content_type_picks_the_patch_format() {
+        let merge_body = r#"{"name":"Grace"}"#;
+        let json_body = r#"[{"op":"replace","path":"/name","value":"Grace"}]"#;
+        let cases = [
+            (None, merge_body, "merge"),
+            (Some("application/merge-patch+json"), merge_body, "merge"),
+            (Some("application/merge-patch+json; charset=utf-8"), merge_body, "merge"),
+            (Some("application/json"), merge_body, "merge"),
+            (Some("Application/JSON-Patch+JSON"), json_body, "json"),
+            (Some("application/json-patch+json"), json_body, "json"),
+            (Some("application/json-patch+json"), merge_body, "invalid json"),
+            (Some("application/merge-patch+json"), "{", "invalid json"),
+            (Some("text/plain"), merge_body, "unsupported"),
+            (Some("application/xml"), "<user/>", "unsupported"),
+            (Some(""), merge_body, "unsupported"),
+        ];
+
+        for (content_type, body, expected) in cases {
+            let found = match get_patch_from_request_body(&patch_request(content_type, body)) {
+                Ok(UserPatch::Merge(_)) => "merge",
+                Ok(UserPatch::Json(_)) => "json",
+                Err(PatchBodyError::Json(_)) => "invalid json",
+                Err(PatchBodyError::UnsupportedMediaType) => "unsupported",
+            };
+            assert_eq!(found, expected, "{:?}", content_type);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::http::Query;
+use crate::models::User;
+use crate::validation::FieldError;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Id,
+    Name,
+    Email,
+    UpdatedAt,
+}
+
+impl SortField {
+    pub const ALL: [SortField; 4] = [SortField::Id, SortField::Name, SortField::Email, SortField::UpdatedAt];
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.as_str() == name)
+    }
+
+    // Doubles as the column name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortField::Id => "id",
+            SortField::Name => "name",
+            SortField::Email => "email",
+            SortField::UpdatedAt => "updated_at",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+/// Listing options for `GET /users`, read from the query string:
+/// `limit`, `offset` or keyset `after=<id>`, `sort=name,-id`, the
+/// `email_domain`, `name_contains` and `updated_since` filters, and
+/// `include_deleted`.
+#[derive(Debug, Clone)]
+pub struct UserQuery {
+    pub limit: i64,
+    pub offset: i64,
+    pub after: Option<i32>,
+    // Never empty, and always ends with `id` so the order is total.
+    pub sort: Vec<SortKey>,
+    pub email_domain: Option<String>,
+    pub name_contains: Option<String>,
+    // Inclusive, so a sync job that resumes from the last `updated_at` it
+    // saw may get that row again but never misses one.
+    pub updated_since: Option<DateTime<Utc>>,
+    pub include_deleted: bool,
+}
+
+/// One page of a user listing. The repository reads one row past `limit`
+/// to find out whether another page follows.
+#[derive(Debug, Clone)]
+pub struct UserPage {
+    pub users: Vec<User>,
+    pub total: i64,
+    pub has_more: bool,
+}
+
+impl UserQuery {
+    pub fn from_query(query: &Query) -> Result<Self, Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        let limit = parse_number(query, "limit", DEFAULT_LIMIT, &mut errors);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            errors.push(invalid("limit", format!("limit must be between 1 and {}", MAX_LIMIT)));
+        }
+
+        let offset = parse_number(query, "offset", 0, &mut errors);
+        if offset < 0 {
+            errors.push(invalid("offset", "offset must not be negative".to_string()));
+        }
+
+        let after = match query.get("after") {
+            Some(value) => match value.parse::<i32>() {
+                Ok(id) => Some(id),
+                Err(_) => {
+                    errors.push(invalid("after", "after must be a user id".to_string()));
+                    None
+                }
+            },
+            None => None,
+        };
+        if after.is_some() && query.get("offset").is_some() {
+            errors.push(invalid("after", "after cannot be combined with offset".to_string()));
+        }
+
+        let mut sort = Vec::new();
+        for key in query.get("sort").unwrap_or("id").split(',') {
+            let (name, descending) = match key.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (key, false),
+            };
+            match SortField::parse(name) {
+                Some(field) if !sort.iter().any(|key: &SortKey| key.field == field) => {
+                    sort.push(SortKey { field, descending })
+                }
+                Some(_) => errors.push(invalid("sort", format!("sort lists `{}` more than once", name))),
+                None => errors.push(invalid(
+                    "sort",
+                    format!("cannot sort by `{}`; use id, name, email or updated_at", name),
+                )),
+            }
+        }
+        // Ties are broken by id in the direction of the last key.
+        if !sort.iter().any(|key| key.field == SortField::Id) {
+            let descending = sort.last().is_some_and(|key| key.descending);
+            sort.push(SortKey {
+                field: SortField::Id,
+                descending,
+            });
+        }
+        // Keyset pagination compares the whole sort tuple at once.
+        if after.is_some() && sort.iter().any(|key| key.descending != sort[0].descending) {
+            errors.push(invalid("sort", "after needs every sort key in the same direction".to_string()));
+        }
+
+        let filter = |name: &str| query.get(name).filter(|value| !value.is_empty()).map(str::to_string);
+        let email_domain = filter("email_domain");
+        let name_contains = filter("name_contains");
+        let updated_since = match query.get("updated_since") {
+            Some(value) => match DateTime::parse_from_rfc3339(value) {
+                Ok(since) => Some(since.with_timezone(&Utc)),
+                Err(_) => {
+                    errors.push(invalid(
+                        "updated_since",
+                        "updated_since must be an RFC 3339 timestamp".to_string(),
+                    ));
+                    None
+                }
+            },
+            None => None,
+        };
+        let include_deleted = include_deleted(query).unwrap_or_else(|error| {
+            errors.push(error);
+            false
+        });
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Self {
+            limit,
+            offset,
+            after,
+            sort,
+            email_domain,
+            name_contains,
+            updated_since,
+            include_deleted,
+        })
+    }
+
+    /// Query parameters that every page link repeats; the caller adds
+    /// `offset` or `after`.
+    pub fn link_params(&self) -> Vec<(&'static str, String)> {
+        let sort = self
+            .sort
+            .iter()
+            .map(|key| format!("{}{}", if key.descending { "-" } else { "" }, key.field.as_str()))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut params = vec![("limit", self.limit.to_string()), ("sort", sort)];
+        if let Some(domain) = &self.email_domain {
+            params.push(("email_domain", domain.clone()));
+        }
+        if let Some(name) = &self.name_contains {
+            params.push(("name_contains", name.clone()));
+        }
+        if let Some(since) = &self.updated_since {
+            params.push(("updated_since", since.to_rfc3339_opts(SecondsFormat::AutoSi, true)));
+        }
+        if self.include_deleted {
+            params.push(("include_deleted", "true".to_string()));
+        }
+        params
+    }
+}
+
+/// `include_deleted=true|false`, which `GET /users/:id` takes as well.
+pub fn include_deleted(query: &Query) -> Result<bool, FieldError> {
+    match query.get("include_deleted") {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(_) => Err(invalid("include_deleted", "include_deleted must be true or false".to_string())),
+    }
+}
+
+fn parse_number(query: &Query, name: &'static str, default: i64, errors: &mut Vec<FieldError>) -> i64 {
+    match query.get(name) {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            errors.push(invalid(name, format!("{} must be a whole number", name)));
+            default
+        }),
+        None => default,
+    }
+}
+
+fn invalid(field: &'static str, message: String) -> FieldError {
+    FieldError {
+        field,
+        code: "invalid",
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<UserQuery, Vec<String>> {
+        UserQuery::from_query(&Query::parse(raw))
+            .map_err(|errors| errors.into_iter().map(|error| format!("{}: {}", error.field, error.message)).collect())
+    }
+
+    // The sort keys as they would appear in a link, e.g. `name,-id`.
+    fn sort(raw: &str) -> String {
+        let query = parse(raw).unwrap();
+        query.link_params().into_iter().find(|(name, _)| *name == "sort").unwrap().1
+    }
+
+    #[test]
+    fn defaults_when_nothing_is_given() {
+        let query = parse("").unwrap();
+        assert_eq!(query.limit, DEFAULT_LIMIT);
+        assert_eq!(query.offset, 0);
+        assert_eq!(query.after, None);
+        assert_eq!(query.sort, [SortKey { field: SortField::Id, descending: false }]);
+        assert!(!query.include_deleted);
+    }
+
+    #[test]
+    fn bounds_the_limit() {
+        let cases = [
+            ("limit=1", Ok(1)),
+            ("limit=100", Ok(MAX_LIMIT)),
+            ("limit=0", Err("limit: limit must be between 1 and 100")),
+            ("limit=101", Err("limit: limit must be between 1 and 100")),
+            ("limit=-5", Err("limit: limit must be between 1 and 100")),
+            ("limit=ten", Err("limit: limit must be a whole number")),
+            ("limit=", Err("limit: limit must be a whole number")),
+            ("offset=-1", Err("offset: offset must not be negative")),
+        ];
+
+        for (raw, expected) in cases {
+            let found = parse(raw).map(|query| query.limit);
+            assert_eq!(found, expected.map_err(|message| vec![message.to_string()]), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn after_cannot_be_combined_with_offset() {
+        assert_eq!(parse("after=5").unwrap().after, Some(5));
+        assert_eq!(parse("offset=20").unwrap().offset, 20);
+        assert_eq!(
+            parse("after=5&offset=0").unwrap_err(),
+            ["after: after cannot be combined with offset"]
+        );
+        assert_eq!(parse("after=five").unwrap_err(), ["after: after must be a user id"]);
+    }
+
+    #[test]
+    fn keyset_paging_needs_one_sort_direction() {
+        let mixed = "sort: after needs every sort key in the same direction";
+        let cases = [
+            ("sort=name,-email", Ok("name,-email,-id")),
+            ("sort=-name", Ok("-name,-id")),
+            ("sort=-name&after=3", Ok("-name,-id")),
+            ("sort=name,email&after=3", Ok("name,email,id")),
+            ("sort=-updated_at,-id&after=3", Ok("-updated_at,-id")),
+            ("sort=name,-email&after=3", Err(mixed)),
+            ("sort=-name,id&after=3", Err(mixed)),
+        ];
+
+        for (raw, expected) in cases {
+            let found = parse(raw).map(|_| sort(raw));
+            let expected = expected.map(str::to_string).map_err(|message| vec![message.to_string()]);
+            assert_eq!(found, expected, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_and_repeated_sort_keys() {
+        assert_eq!(
+            parse("sort=age").unwrap_err(),
+            ["sort: cannot sort by `age`; use id, name, email or updated_at"]
+        );
+        assert_eq!(
+            parse("sort=name,").unwrap_err(),
+            ["sort: cannot sort by ``; use id, name, email or updated_at"]
+        );
+        assert_eq!(parse("sort=name,-name").unwrap_err(), ["sort: sort lists `name` more than once"]);
+        // Every problem is reported, not just the first.
+        assert_eq!(parse("sort=Name&limit=0").unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn link_params_repeat_the_filters() {
+        let raw = "limit=5&sort=-email&email_domain=example.com&name_contains=\
+                   &updated_since=2024-01-02T03:04:05%2B01:00&include_deleted=true";
+        let query = parse(raw).unwrap();
+        assert_eq!(
+            query.link_params(),
+            [
+                ("limit", "5".to_string()),
+                ("sort", "-email,-id".to_string()),
+                ("email_domain", "example.com".to_string()),
+                ("updated_since", "2024-01-02T02:04:05Z".to_string()),
+                ("include_deleted", "true".to_string()),
+            ]
+        );
+    }
+}
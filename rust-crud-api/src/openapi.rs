@@ -0,0 +1,565 @@
+use crate::http::Method;
+use crate::models::user::MAX_NAME_LENGTH;
+use crate::models::user_query::{SortField, DEFAULT_LIMIT, MAX_LIMIT};
+use crate::utils::{JSON_PATCH, MERGE_PATCH};
+use serde_json::{json, Map, Value};
+
+/// The OpenAPI 3.0 description of `routes`, as the router lists them. Paths
+/// and their parameters come from the patterns themselves; what each route
+/// takes and returns is looked up in `operation` below.
+pub fn document<'a>(routes: impl Iterator<Item = (Method, &'a str)>) -> Value {
+    let mut paths = Map::new();
+    for (method, pattern) in routes {
+        let mut operation = operation(method, pattern).unwrap_or_else(|| {
+            json!({
+                "summary": "Undocumented",
+                "responses": { "default": { "description": "Not described yet" } },
+            })
+        });
+
+        let path_params: Vec<Value> = pattern
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix(':'))
+            .map(path_param)
+            .collect();
+        if !path_params.is_empty() {
+            let params = operation["parameters"].as_array().cloned().unwrap_or_default();
+            operation["parameters"] = Value::Array(path_params.into_iter().chain(params).collect());
+        }
+
+        let path = paths.entry(openapi_path(pattern)).or_insert_with(|| json!({}));
+        path[method.as_str().to_ascii_lowercase()] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Users with optimistic concurrency via ETag/If-Match, soft deletes and \
+                            RFC 7807 problem details for every error.",
+        },
+        "paths": paths,
+        "components": components(),
+    })
+}
+
+// `/users/:id` is written `/users/{id}` in OpenAPI.
+fn openapi_path(pattern: &str) -> String {
+    pattern
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn path_param(name: &str) -> Value {
+    let schema = match name {
+        "id" => json!({ "type": "integer", "format": "int32" }),
+        _ => json!({ "type": "string" }),
+    };
+    json!({ "name": name, "in": "path", "required": true, "schema": schema })
+}
+
+fn schema(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn response(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/responses/{}", name) })
+}
+
+fn parameter(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/parameters/{}", name) })
+}
+
+fn json_body(description: &str, schema_name: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema(schema_name) } },
+    })
+}
+
+// A user with its current version in `ETag`.
+fn user_response(description: &str) -> Value {
+    let mut body = json_body(description, "User");
+    body["headers"] = json!({ "ETag": { "$ref": "#/components/headers/ETag" } });
+    body
+}
+
+const ADMIN: &str = "adminToken";
+
+fn admin_only() -> Value {
+    json!([{ ADMIN: [] }])
+}
+
+fn operation(method: Method, pattern: &str) -> Option<Value> {
+    let operation = match (method, pattern) {
+        (Method::Post, "/users") => json!({
+            "operationId": "createUser",
+            "tags": ["users"],
+            "summary": "Create a user",
+            "requestBody": { "required": true, "content": { "application/json": { "schema": schema("User") } } },
+            "responses": {
+                "201": {
+                    "description": "The user as stored",
+                    "headers": {
+                        "ETag": { "$ref": "#/components/headers/ETag" },
+                        "Location": { "description": "Where the user can be fetched", "schema": { "type": "string" } },
+                    },
+                    "content": { "application/json": { "schema": schema("User") } },
+                },
+                "400": response("BadRequest"),
+                "409": response("Conflict"),
+                "422": response("ValidationFailed"),
+                "500": response("InternalError"),
+                "503": response("TransactionConflict"),
+            },
+        }),
+        (Method::Get, "/users") => json!({
+            "operationId": "listUsers",
+            "tags": ["users"],
+            "summary": "List users a page at a time",
+            "description": "Pages by `offset`, or by keyset with `after` for stable paging through changing data. \
+                            `include_deleted=true` needs the admin token.",
+            "parameters": [
+                {
+                    "name": "limit", "in": "query",
+                    "schema": { "type": "integer", "minimum": 1, "maximum": MAX_LIMIT, "default": DEFAULT_LIMIT },
+                },
+                { "name": "offset", "in": "query", "schema": { "type": "integer", "minimum": 0, "default": 0 } },
+                {
+                    "name": "after", "in": "query",
+                    "description": "Id of the last user on the previous page; cannot be combined with `offset`.",
+                    "schema": { "type": "integer", "format": "int32" },
+                },
+                {
+                    "name": "sort", "in": "query",
+                    "description": format!(
+                        "Comma-separated keys out of {}, each descending with a leading `-`.",
+                        SortField::ALL.map(|field| format!("`{}`", field.as_str())).join(", "),
+                    ),
+                    "schema": { "type": "string", "default": "id" },
+                },
+                {
+                    "name": "email_domain", "in": "query",
+                    "description": "Only users whose email is at this domain.",
+                    "schema": { "type": "string" },
+                },
+                {
+                    "name": "name_contains", "in": "query",
+                    "description": "Only users whose name contains this, ignoring case.",
+                    "schema": { "type": "string" },
+                },
+                {
+                    "name": "updated_since", "in": "query",
+                    "description": "Only users updated at or after this time.",
+                    "schema": { "type": "string", "format": "date-time" },
+                },
+                parameter("IncludeDeleted"),
+            ],
+            "responses": {
+                "200": json_body("One page of users", "UserPage"),
+                "400": response("InvalidQuery"),
+                "401": response("Unauthorized"),
+                "403": response("Forbidden"),
+                "500": response("InternalError"),
+            },
+        }),
+        (Method::Get, "/users/:id") => json!({
+            "operationId": "getUser",
+            "tags": ["users"],
+            "summary": "Fetch a user",
+            "parameters": [parameter("IncludeDeleted"), parameter("IfNoneMatch")],
+            "responses": {
+                "200": user_response("The user"),
+                "304": { "description": "The user is still at the version named in `If-None-Match`" },
+                "400": response("BadRequest"),
+                "401": response("Unauthorized"),
+                "403": response("Forbidden"),
+                "404": response("NotFound"),
+                "500": response("InternalError"),
+            },
+        }),
+        (Method::Put, "/users/:id") => json!({
+            "operationId": "updateUser",
+            "tags": ["users"],
+            "summary": "Replace a user's fields",
+            "parameters": [parameter("IfMatch")],
+            "requestBody": { "required": true, "content": { "application/json": { "schema": schema("User") } } },
+            "responses": {
+                "200": user_response("The updated user"),
+                "400": response("BadRequest"),
+                "404": response("NotFound"),
+                "409": response("Conflict"),
+                "412": response("PreconditionFailed"),
+                "422": response("ValidationFailed"),
+                "500": response("InternalError"),
+                "503": response("TransactionConflict"),
+            },
+        }),
+        (Method::Patch, "/users/:id") => json!({
+            "operationId": "patchUser",
+            "tags": ["users"],
+            "summary": "Change some of a user's fields",
+            "description": "A JSON Merge Patch (RFC 7396, also taken as plain `application/json`) or a \
+                            JSON Patch (RFC 6902) applied all or nothing.",
+            "parameters": [parameter("IfMatch")],
+            "requestBody": {
+                "required": true,
+                "content": {
+                    MERGE_PATCH: { "schema": { "type": "object" } },
+                    JSON_PATCH: { "schema": schema("JsonPatch") },
+                },
+            },
+            "responses": {
+                "200": user_response("The patched user"),
+                "400": response("BadRequest"),
+                "404": response("NotFound"),
+                "409": response("Conflict"),
+                "412": response("PreconditionFailed"),
+                "415": response("UnsupportedMediaType"),
+                "422": response("ValidationFailed"),
+                "500": response("InternalError"),
+                "503": response("TransactionConflict"),
+            },
+        }),
+        (Method::Delete, "/users/:id") => json!({
+            "operationId": "deleteUser",
+            "tags": ["users"],
+            "summary": "Soft-delete a user",
+            "description": "The user is hidden until purged after the retention period, and can be restored until then.",
+            "parameters": [parameter("IfMatch")],
+            "responses": {
+                "200": { "description": "Deleted", "content": { "text/plain": { "schema": { "type": "string" } } } },
+                "400": response("BadRequest"),
+                "404": response("NotFound"),
+                "412": response("PreconditionFailed"),
+                "500": response("InternalError"),
+                "503": response("TransactionConflict"),
+            },
+        }),
+        (Method::Post, "/users/:id/restore") => json!({
+            "operationId": "restoreUser",
+            "tags": ["users"],
+            "summary": "Undo a soft delete",
+            "security": admin_only(),
+            "responses": {
+                "200": user_response("The restored user"),
+                "400": response("BadRequest"),
+                "401": response("Unauthorized"),
+                "403": response("Forbidden"),
+                "404": response("NotFound"),
+                "409": response("Conflict"),
+                "500": response("InternalError"),
+            },
+        }),
+        (Method::Post, "/users/purge") => json!({
+            "operationId": "purgeUsers",
+            "tags": ["users"],
+            "summary": "Hard-delete users whose retention period has run out",
+            "security": admin_only(),
+            "responses": {
+                "200": {
+                    "description": "How many users were removed",
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "required": ["purged"],
+                        "properties": { "purged": { "type": "integer", "format": "int64" } },
+                    } } },
+                },
+                "401": response("Unauthorized"),
+                "403": response("Forbidden"),
+                "500": response("InternalError"),
+            },
+        }),
+        (Method::Get, "/healthz") => json!({
+            "operationId": "liveness",
+            "tags": ["operations"],
+            "summary": "Whether the process is up",
+            "responses": { "200": json_body("Alive", "Check") },
+        }),
+        (Method::Get, "/readyz") => json!({
+            "operationId": "readiness",
+            "tags": ["operations"],
+            "summary": "Whether the server should be sent traffic",
+            "description": "Ready when the database answers, its schema is current and no shutdown has started.",
+            "responses": {
+                "200": json_body("Ready", "Readiness"),
+                "503": json_body("Not ready; the failing checks say why", "Readiness"),
+            },
+        }),
+        (Method::Get, "/metrics") => json!({
+            "operationId": "metrics",
+            "tags": ["operations"],
+            "summary": "Prometheus metrics",
+            "responses": {
+                "200": {
+                    "description": "Metrics in the Prometheus text format",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+            },
+        }),
+        (Method::Get, "/openapi.json") => json!({
+            "operationId": "openapi",
+            "tags": ["operations"],
+            "summary": "This document",
+            "responses": {
+                "200": { "description": "OpenAPI 3 document", "content": { "application/json": {} } },
+            },
+        }),
+        (Method::Get, "/docs") => json!({
+            "operationId": "docs",
+            "tags": ["operations"],
+            "summary": "Browsable API documentation",
+            "responses": {
+                "200": { "description": "Swagger UI for this document", "content": { "text/html": {} } },
+            },
+        }),
+        (Method::Get, "/docs/swagger-ui.css") => json!({
+            "operationId": "docsStylesheet",
+            "tags": ["operations"],
+            "summary": "Stylesheet of the documentation page",
+            "responses": {
+                "200": { "description": "The Swagger UI stylesheet", "content": { "text/css": {} } },
+            },
+        }),
+        (Method::Get, "/docs/swagger-ui-bundle.js") => json!({
+            "operationId": "docsScript",
+            "tags": ["operations"],
+            "summary": "Script of the documentation page",
+            "responses": {
+                "200": { "description": "The Swagger UI bundle", "content": { "text/javascript": {} } },
+            },
+        }),
+        (Method::Get, "/docs/swagger-ui-bundle.js.LICENSE.txt") => json!({
+            "operationId": "docsScriptLicenses",
+            "tags": ["operations"],
+            "summary": "Licenses of the code in the Swagger UI bundle",
+            "responses": {
+                "200": {
+                    "description": "The packages in the bundle and their licenses",
+                    "content": { "text/plain": {} },
+                },
+            },
+        }),
+        _ => return None,
+    };
+    Some(operation)
+}
+
+fn problem_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/problem+json": { "schema": schema("Problem") } },
+    })
+}
+
+fn components() -> Value {
+    json!({
+        "schemas": {
+            "User": {
+                "type": "object",
+                "required": ["id", "name", "email", "created_at", "updated_at", "version"],
+                "properties": {
+                    "id": { "type": "integer", "format": "int32", "readOnly": true },
+                    "name": { "type": "string", "minLength": 1, "maxLength": MAX_NAME_LENGTH },
+                    "email": { "type": "string", "format": "email" },
+                    "created_at": { "type": "string", "format": "date-time", "readOnly": true },
+                    "updated_at": { "type": "string", "format": "date-time", "readOnly": true },
+                    "version": {
+                        "type": "integer", "format": "int64", "readOnly": true,
+                        "description": "Bumped on every write; the `ETag` of the user.",
+                    },
+                    "deleted_at": {
+                        "type": "string", "format": "date-time", "readOnly": true,
+                        "description": "Only present on soft-deleted users.",
+                    },
+                },
+            },
+            "UserPage": {
+                "type": "object",
+                "required": ["items", "total", "limit", "next", "prev"],
+                "properties": {
+                    "items": { "type": "array", "items": schema("User") },
+                    "total": { "type": "integer", "format": "int64", "description": "Matching users on every page." },
+                    "limit": { "type": "integer" },
+                    "next": { "type": "string", "nullable": true, "description": "Link to the next page." },
+                    "prev": { "type": "string", "nullable": true, "description": "Link to the previous page." },
+                },
+            },
+            "JsonPatch": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["op", "path"],
+                    "properties": {
+                        "op": { "type": "string", "enum": ["add", "remove", "replace", "move", "copy", "test"] },
+                        "path": { "type": "string" },
+                        "from": { "type": "string" },
+                        "value": {},
+                    },
+                },
+            },
+            "Problem": {
+                "type": "object",
+                "description": "RFC 7807 problem details. Branch on `code`, which is stable across releases.",
+                "required": ["type", "title", "status", "code"],
+                "properties": {
+                    "type": { "type": "string", "example": "about:blank" },
+                    "title": { "type": "string" },
+                    "status": { "type": "integer" },
+                    "code": { "type": "string", "example": "validation_failed" },
+                    "detail": { "type": "string" },
+                    "errors": { "type": "array", "items": schema("FieldError") },
+                },
+            },
+            "FieldError": {
+                "type": "object",
+                "required": ["field", "code", "message"],
+                "properties": {
+                    "field": { "type": "string" },
+                    "code": { "type": "string", "example": "required" },
+                    "message": { "type": "string" },
+                },
+            },
+            "Check": {
+                "type": "object",
+                "required": ["status"],
+                "properties": {
+                    "status": { "type": "string" },
+                    "latency_ms": { "type": "number" },
+                    "pending": { "type": "integer" },
+                },
+            },
+            "Readiness": {
+                "type": "object",
+                "required": ["status", "checks"],
+                "properties": {
+                    "status": { "type": "string", "enum": ["ready", "not_ready"] },
+                    "checks": {
+                        "type": "object",
+                        "properties": {
+                            "database": schema("Check"),
+                            "migrations": schema("Check"),
+                            "shutdown": schema("Check"),
+                        },
+                    },
+                },
+            },
+        },
+        "responses": {
+            "BadRequest": problem_response("Malformed JSON body or user id"),
+            "InvalidQuery": problem_response("One or more query parameters are invalid"),
+            "Unauthorized": problem_response("An admin token is needed"),
+            "Forbidden": problem_response("The admin token is not valid"),
+            "NotFound": problem_response("No such user"),
+            "Conflict": problem_response("Clashes with existing data, e.g. an email in use, or a patch failed"),
+            "PreconditionFailed": problem_response("The user has changed since the version in `If-Match`"),
+            "UnsupportedMediaType": problem_response("The body is not one of the accepted media types"),
+            "ValidationFailed": problem_response("One or more fields are invalid; `errors` lists them all"),
+            "InternalError": problem_response("The request could not be completed"),
+            "TransactionConflict": problem_response("A concurrent update got in the way; retry after `Retry-After`"),
+        },
+        "parameters": {
+            "IfMatch": {
+                "name": "If-Match", "in": "header",
+                "description": "Only change the user if it is still at one of these versions.",
+                "schema": { "type": "string" },
+            },
+            "IfNoneMatch": {
+                "name": "If-None-Match", "in": "header",
+                "description": "Answer 304 if the user is still at one of these versions.",
+                "schema": { "type": "string" },
+            },
+            "IncludeDeleted": {
+                "name": "include_deleted", "in": "query",
+                "description": "Also return soft-deleted users; needs the admin token.",
+                "schema": { "type": "boolean", "default": false },
+            },
+        },
+        "headers": {
+            "ETag": { "description": "The user's version, for `If-Match`", "schema": { "type": "string" } },
+        },
+        "securitySchemes": {
+            ADMIN: { "type": "http", "scheme": "bearer", "description": "The configured admin token" },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::AdminConfig;
+    use crate::controllers::{HealthController, MetricsController, UserController};
+    use crate::http::request::Version;
+    use crate::http::{Headers, Method, PathParams, Query, Request, RouteMatch, StatusCode, TrailingSlash};
+    use crate::repositories::InMemoryUserRepository;
+    use crate::routes;
+    use crate::services::UserService;
+    use crate::shutdown::Shutdown;
+    use crate::utils::block_on;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    // Fetches `/openapi.json` through the router the servers use.
+    fn served_document(docs_page: bool) -> Value {
+        let user_service = Arc::new(UserService::new(Box::new(InMemoryUserRepository::new())));
+        let admin = AdminConfig {
+            token: None,
+            retention_days: 30,
+            purge_interval: None,
+        };
+        let router = routes::build_router(
+            Arc::new(UserController::new(Arc::clone(&user_service), admin)),
+            Arc::new(HealthController::new(user_service, Shutdown::new())),
+            Arc::new(MetricsController::new(|| None)),
+            TrailingSlash::Ignore,
+            docs_page,
+        );
+
+        let handler = match router.find(Method::Get, "/openapi.json") {
+            RouteMatch::Found(handler, _, _) => handler,
+            _ => panic!("/openapi.json is not routed"),
+        };
+        let response = block_on(handler(Request {
+            method: Method::Get,
+            path: "/openapi.json".to_string(),
+            query: Query::default(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: PathParams::default(),
+        }));
+        assert_eq!(response.status, StatusCode::Ok);
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        // Builds without the docs-ui feature cannot turn the page on.
+        for docs_page in [false, cfg!(feature = "docs-ui")] {
+            let document = served_document(docs_page);
+            let paths = document["paths"].as_object().unwrap();
+            assert_eq!(paths.contains_key("/docs"), docs_page);
+
+            for (path, item) in paths {
+                for (method, operation) in item.as_object().unwrap() {
+                    assert_ne!(operation["summary"], "Undocumented", "{} {}", method, path);
+                    assert!(operation["operationId"].is_string(), "{} {} has no operationId", method, path);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn path_parameters_come_from_the_pattern() {
+        let document = served_document(false);
+        let get = &document["paths"]["/users/{id}"]["get"];
+        assert_eq!(get["parameters"][0]["name"], "id");
+        assert_eq!(get["parameters"][0]["in"], "path");
+        assert!(document["paths"].get("/users/:id").is_none());
+    }
+}
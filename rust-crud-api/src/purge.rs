@@ -0,0 +1,57 @@
+use crate::logging;
+use crate::services::user_service::ServiceError;
+use crate::services::UserService;
+#[cfg(not(feature = "async"))]
+use crate::utils::block_on;
+#[cfg(not(feature = "async"))]
+use std::io;
+use std::sync::Arc;
+#[cfg(not(feature = "async"))]
+use std::thread;
+use std::time::Duration;
+
+/// Starts the background job that hard-deletes users soft-deleted more than
+/// `retention_days` ago, once at startup and then every `interval`. It only
+/// holds a weak handle, so it neither keeps the pool open at shutdown nor
+/// outlives the service.
+#[cfg(not(feature = "async"))]
+pub fn spawn(user_service: &Arc<UserService>, retention_days: i32, interval: Duration) -> io::Result<()> {
+    let user_service = Arc::downgrade(user_service);
+    thread::Builder::new()
+        .name("user-purge".to_string())
+        .spawn(move || {
+            while let Some(service) = user_service.upgrade() {
+                report(block_on(service.purge_deleted(retention_days)));
+                drop(service);
+                thread::sleep(interval);
+            }
+        })?;
+    Ok(())
+}
+
+/// Async counterpart of `spawn`, run as a task on the current runtime.
+#[cfg(feature = "async")]
+pub fn spawn_async(user_service: &Arc<UserService>, retention_days: i32, interval: Duration) {
+    let user_service = Arc::downgrade(user_service);
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            match user_service.upgrade() {
+                Some(service) => report(service.purge_deleted(retention_days).await),
+                None => return,
+            }
+        }
+    });
+}
+
+fn report(result: Result<u64, ServiceError>) {
+    match result {
+        Ok(0) => {}
+        Ok(purged) => logging::info!("purged deleted users", purged = purged),
+        Err(ServiceError::DatabaseError(e)) => {
+            logging::error!("failed to purge deleted users", error = logging::error_chain(&e));
+        }
+        Err(e) => logging::error!("failed to purge deleted users", error = format!("{:?}", e)),
+    }
+}
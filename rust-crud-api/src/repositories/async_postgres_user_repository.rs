@@ -0,0 +1,140 @@
+use crate::models::{User, UserChanges, UserPage, UserQuery};
+use crate::database::{AsyncDatabase, PoolState};
+use crate::migrations;
+use crate::repositories::list_statement::ListStatement;
+use crate::repositories::postgres_user_repository::{
+    delete_statement, find_by_id_statement, insert_statement, list_params, restore_statement, update_fields_statement,
+    update_statement, user_from_row, LIST_DIALECT, PING_STATEMENT, PURGE_STATEMENT,
+};
+use crate::repositories::{AsyncUserRepository, RepositoryFuture};
+
+/// The Postgres store on tokio-postgres, for the async server. It runs the
+/// same statements as `PostgresUserRepository`.
+pub struct AsyncPostgresUserRepository {
+    db: AsyncDatabase,
+}
+
+impl AsyncPostgresUserRepository {
+    pub fn new(db: AsyncDatabase) -> Self {
+        Self { db }
+    }
+}
+
+impl AsyncUserRepository for AsyncPostgresUserRepository {
+
+    fn create<'a>(&'a self, user: &'a User) -> RepositoryFuture<'a, User> {
+        Box::pin(async move {
+            let row = self.db.get_client().await?.query_one(
+                &insert_statement(),
+                &[&user.name, &user.email]
+            ).await?;
+            Ok(user_from_row(&row))
+        })
+    }
+
+    fn find_by_id(&self, id: i32, include_deleted: bool) -> RepositoryFuture<'_, Option<User>> {
+        Box::pin(async move {
+            let row = self.db.get_client().await?.query_opt(
+                &find_by_id_statement(),
+                &[&id, &include_deleted],
+            ).await?;
+
+            Ok(row.as_ref().map(user_from_row))
+        })
+    }
+
+    fn find_page<'a>(&'a self, query: &'a UserQuery) -> RepositoryFuture<'a, UserPage> {
+        Box::pin(async move {
+            let statement = ListStatement::new(query, &LIST_DIALECT);
+            let params = list_params(&statement);
+            let client = self.db.get_client().await?;
+
+            let total: i64 = client
+                .query_one(&statement.count, &params[..statement.filter_params])
+                .await?
+                .get(0);
+            let users = client
+                .query(&statement.select, &params)
+                .await?
+                .iter()
+                .map(user_from_row)
+                .collect();
+
+            Ok(statement.page(users, total))
+        })
+    }
+
+    // With `versions`, only a row at one of those versions is updated; the
+    // check and the write are one statement, so no other writer can slip in.
+    fn update<'a>(&'a self, id: i32, user: &'a User, versions: Option<&'a [i64]>) -> RepositoryFuture<'a, Option<User>> {
+        Box::pin(async move {
+            let row = self.db.get_client().await?.query_opt(
+                &update_statement(),
+                &[&user.name, &user.email, &id, &versions],
+            ).await?;
+            Ok(row.as_ref().map(user_from_row))
+        })
+    }
+
+    // Only updates the row if it is still at `version`, the one `changes`
+    // were computed from.
+    fn update_fields<'a>(&'a self, id: i32, changes: &'a UserChanges, version: i64) -> RepositoryFuture<'a, Option<User>> {
+        Box::pin(async move {
+            let (sql, params) = update_fields_statement(&id, changes, &version);
+            let row = self.db.get_client().await?.query_opt(&sql, &params).await?;
+            Ok(row.as_ref().map(user_from_row))
+        })
+    }
+
+    // Soft delete: the row stays, hidden, until `purge_deleted` removes it.
+    fn delete<'a>(&'a self, id: i32, versions: Option<&'a [i64]>) -> RepositoryFuture<'a, u64> {
+        Box::pin(async move {
+            let rows_affected = self.db.get_client().await?.execute(
+                &delete_statement(),
+                &[&id, &versions]
+            ).await?;
+            Ok(rows_affected)
+        })
+    }
+
+    // None unless the user exists and is deleted.
+    fn restore(&self, id: i32) -> RepositoryFuture<'_, Option<User>> {
+        Box::pin(async move {
+            let row = self.db.get_client().await?.query_opt(
+                &restore_statement(),
+                &[&id],
+            ).await?;
+            Ok(row.as_ref().map(user_from_row))
+        })
+    }
+
+    // Hard-deletes users that were deleted more than `retention_days` ago.
+    fn purge_deleted(&self, retention_days: i32) -> RepositoryFuture<'_, u64> {
+        Box::pin(async move {
+            let rows_affected = self.db.get_client().await?.execute(
+                PURGE_STATEMENT,
+                &[&retention_days],
+            ).await?;
+            Ok(rows_affected)
+        })
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        Some(self.db.state())
+    }
+
+    fn ping(&self) -> RepositoryFuture<'_, ()> {
+        Box::pin(async move {
+            self.db.get_client().await?.batch_execute(PING_STATEMENT).await?;
+            Ok(())
+        })
+    }
+
+    fn pending_migrations(&self) -> RepositoryFuture<'_, usize> {
+        Box::pin(async move {
+            let rows = self.db.get_client().await?.query(migrations::APPLIED_VERSIONS, &[]).await?;
+            let applied: Vec<i64> = rows.iter().map(|row| row.get(0)).collect();
+            Ok(migrations::pending(&applied))
+        })
+    }
+}
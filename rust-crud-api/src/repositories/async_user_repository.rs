@@ -0,0 +1,87 @@
+use crate::database::{DatabaseError, PoolState};
+use crate::models::{User, UserChanges, UserPage, UserQuery};
+use crate::repositories::UserRepository;
+use std::future::Future;
+use std::pin::Pin;
+
+pub type RepositoryFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, DatabaseError>> + Send + 'a>>;
+
+/// What the service stores users through, with the same contract as
+/// `UserRepository`. Every `UserRepository` is one: its calls run when the
+/// future is first polled and finish there, which is what lets the blocking
+/// server drive the service with `block_on`.
+pub trait AsyncUserRepository: Send + Sync {
+    fn create<'a>(&'a self, user: &'a User) -> RepositoryFuture<'a, User>;
+
+    fn find_by_id(&self, id: i32, include_deleted: bool) -> RepositoryFuture<'_, Option<User>>;
+
+    fn find_page<'a>(&'a self, query: &'a UserQuery) -> RepositoryFuture<'a, UserPage>;
+
+    fn update<'a>(&'a self, id: i32, user: &'a User, versions: Option<&'a [i64]>) -> RepositoryFuture<'a, Option<User>>;
+
+    fn update_fields<'a>(&'a self, id: i32, changes: &'a UserChanges, version: i64) -> RepositoryFuture<'a, Option<User>>;
+
+    fn delete<'a>(&'a self, id: i32, versions: Option<&'a [i64]>) -> RepositoryFuture<'a, u64>;
+
+    fn restore(&self, id: i32) -> RepositoryFuture<'_, Option<User>>;
+
+    fn purge_deleted(&self, retention_days: i32) -> RepositoryFuture<'_, u64>;
+
+    fn pool_state(&self) -> Option<PoolState> {
+        None
+    }
+
+    fn ping(&self) -> RepositoryFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn pending_migrations(&self) -> RepositoryFuture<'_, usize> {
+        Box::pin(async { Ok(0) })
+    }
+}
+
+impl<R: UserRepository + ?Sized> AsyncUserRepository for R {
+    fn create<'a>(&'a self, user: &'a User) -> RepositoryFuture<'a, User> {
+        Box::pin(async move { UserRepository::create(self, user) })
+    }
+
+    fn find_by_id(&self, id: i32, include_deleted: bool) -> RepositoryFuture<'_, Option<User>> {
+        Box::pin(async move { UserRepository::find_by_id(self, id, include_deleted) })
+    }
+
+    fn find_page<'a>(&'a self, query: &'a UserQuery) -> RepositoryFuture<'a, UserPage> {
+        Box::pin(async move { UserRepository::find_page(self, query) })
+    }
+
+    fn update<'a>(&'a self, id: i32, user: &'a User, versions: Option<&'a [i64]>) -> RepositoryFuture<'a, Option<User>> {
+        Box::pin(async move { UserRepository::update(self, id, user, versions) })
+    }
+
+    fn update_fields<'a>(&'a self, id: i32, changes: &'a UserChanges, version: i64) -> RepositoryFuture<'a, Option<User>> {
+        Box::pin(async move { UserRepository::update_fields(self, id, changes, version) })
+    }
+
+    fn delete<'a>(&'a self, id: i32, versions: Option<&'a [i64]>) -> RepositoryFuture<'a, u64> {
+        Box::pin(async move { UserRepository::delete(self, id, versions) })
+    }
+
+    fn restore(&self, id: i32) -> RepositoryFuture<'_, Option<User>> {
+        Box::pin(async move { UserRepository::restore(self, id) })
+    }
+
+    fn purge_deleted(&self, retention_days: i32) -> RepositoryFuture<'_, u64> {
+        Box::pin(async move { UserRepository::purge_deleted(self, retention_days) })
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        UserRepository::pool_state(self)
+    }
+
+    fn ping(&self) -> RepositoryFuture<'_, ()> {
+        Box::pin(async move { UserRepository::ping(self) })
+    }
+
+    fn pending_migrations(&self) -> RepositoryFuture<'_, usize> {
+        Box::pin(async move { UserRepository::pending_migrations(self) })
+    }
+}
@@ -0,0 +1,111 @@
+use crate::models::{User, UserPage, UserQuery};
+use crate::repositories::postgres_user_repository::USER_COLUMNS;
+use chrono::{DateTime, Utc};
+
+/// A parameter of a `ListStatement`, which each backend binds its own way.
+pub(crate) enum ListParam {
+    Text(String),
+    Time(DateTime<Utc>),
+    Id(i32),
+    Count(i64),
+}
+
+/// What the list SQL of one backend does differently: how it writes the
+/// `n`th placeholder, and the filters on a placeholder that holds the email
+/// domain or part of the name.
+pub(crate) struct Dialect {
+    pub placeholder: fn(usize) -> String,
+    pub email_domain: fn(&str) -> String,
+    pub name_contains: fn(&str) -> String,
+}
+
+/// SQL for one page of `GET /users`, shared by every repository. `count`
+/// takes only the first `filter_params` parameters.
+pub(crate) struct ListStatement {
+    pub select: String,
+    pub count: String,
+    pub filter_params: usize,
+    pub params: Vec<ListParam>,
+    limit: i64,
+}
+
+impl ListStatement {
+    pub fn new(query: &UserQuery, dialect: &Dialect) -> Self {
+        let mut params = Vec::new();
+        let mut conditions = Vec::new();
+
+        if !query.include_deleted {
+            conditions.push("deleted_at IS NULL".to_string());
+        }
+        if let Some(domain) = &query.email_domain {
+            let domain = bind(&mut params, dialect, ListParam::Text(domain.clone()));
+            conditions.push((dialect.email_domain)(&domain));
+        }
+        if let Some(name) = &query.name_contains {
+            let name = bind(&mut params, dialect, ListParam::Text(name.clone()));
+            conditions.push((dialect.name_contains)(&name));
+        }
+        if let Some(since) = query.updated_since {
+            conditions.push(format!("updated_at >= {}", bind(&mut params, dialect, ListParam::Time(since))));
+        }
+
+        let filter_params = params.len();
+        let count = format!("SELECT COUNT(*) FROM users{}", where_clause(&conditions));
+
+        if let Some(after) = query.after {
+            // Rows past the `after` row in sort order; every key shares one direction.
+            let columns = query.sort.iter().map(|key| key.field.as_str()).collect::<Vec<_>>().join(", ");
+            let operator = if query.sort[0].descending { "<" } else { ">" };
+            conditions.push(format!(
+                "({columns}) {operator} (SELECT {columns} FROM users WHERE id = {})",
+                bind(&mut params, dialect, ListParam::Id(after))
+            ));
+        }
+
+        let order = query
+            .sort
+            .iter()
+            .map(|key| format!("{} {}", key.field.as_str(), if key.descending { "DESC" } else { "ASC" }))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let limit = bind(&mut params, dialect, ListParam::Count(query.limit + 1));
+        let offset = bind(&mut params, dialect, ListParam::Count(query.offset));
+        let select = format!(
+            "SELECT {} FROM users{} ORDER BY {} LIMIT {} OFFSET {}",
+            USER_COLUMNS,
+            where_clause(&conditions),
+            order,
+            limit,
+            offset
+        );
+
+        Self {
+            select,
+            count,
+            filter_params,
+            params,
+            limit: query.limit,
+        }
+    }
+
+    // `select` asks for one row more than the limit to learn if there is a next page.
+    pub fn page(&self, mut users: Vec<User>, total: i64) -> UserPage {
+        let has_more = users.len() as i64 > self.limit;
+        users.truncate(self.limit as usize);
+        UserPage { users, total, has_more }
+    }
+}
+
+// Appends `param` and returns the placeholder that refers to it.
+fn bind(params: &mut Vec<ListParam>, dialect: &Dialect, param: ListParam) -> String {
+    params.push(param);
+    (dialect.placeholder)(params.len())
+}
+
+pub(crate) fn where_clause(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conditions.join(" AND "))
+    }
+}
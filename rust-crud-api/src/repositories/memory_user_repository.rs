@@ -0,0 +1,267 @@
+use crate::database::{ConstraintViolation, DatabaseError};
+use crate::models::user_query::{SortField, SortKey};
+use crate::models::{User, UserChanges, UserPage, UserQuery};
+use crate::repositories::UserRepository;
+use chrono::{Duration, Utc};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+#[derive(Default)]
+struct State {
+    users: BTreeMap<i32, User>,
+    // Like a SERIAL column, ids are never handed out twice.
+    next_id: i32,
+}
+
+/// `UserRepository` backed by a map behind a mutex, for unit tests that must
+/// run without a database.
+#[derive(Default)]
+pub struct InMemoryUserRepository {
+    state: Mutex<State>,
+    // What the readiness checks see; every other call still works.
+    unreachable: bool,
+    pending_migrations: usize,
+}
+
+impl InMemoryUserRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails `ping` and `pending_migrations` as if the database were down.
+    pub fn unreachable(mut self) -> Self {
+        self.unreachable = true;
+        self
+    }
+
+    pub fn with_pending_migrations(mut self, pending: usize) -> Self {
+        self.pending_migrations = pending;
+        self
+    }
+
+    fn check_reachable(&self) -> Result<(), DatabaseError> {
+        if self.unreachable {
+            let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused");
+            return Err(DatabaseError::Pool(Box::new(refused)));
+        }
+        Ok(())
+    }
+
+    // Every operation leaves the map consistent before it can panic, so a
+    // poisoned lock is still safe to use.
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl UserRepository for InMemoryUserRepository {
+    fn create(&self, user: &User) -> Result<User, DatabaseError> {
+        let mut state = self.state();
+        state.check_email(&user.email, None)?;
+
+        state.next_id += 1;
+        let id = state.next_id;
+        let now = Utc::now();
+        let created = User {
+            id: Some(id),
+            name: user.name.clone(),
+            email: user.email.clone(),
+            created_at: Some(now),
+            updated_at: Some(now),
+            version: Some(1),
+            deleted_at: None,
+        };
+        state.users.insert(id, created.clone());
+        Ok(created)
+    }
+
+    fn find_by_id(&self, id: i32, include_deleted: bool) -> Result<Option<User>, DatabaseError> {
+        Ok(self
+            .state()
+            .users
+            .get(&id)
+            .filter(|user| include_deleted || user.deleted_at.is_none())
+            .cloned())
+    }
+
+    fn find_page(&self, query: &UserQuery) -> Result<UserPage, DatabaseError> {
+        let state = self.state();
+
+        let mut users: Vec<&User> = state.users.values().filter(|user| matches_filters(user, query)).collect();
+        let total = users.len() as i64;
+        users.sort_by(|a, b| compare(a, b, &query.sort));
+
+        if let Some(after) = query.after {
+            // Like the SQL row comparison, an unknown `after` id matches nothing.
+            users = match state.users.get(&after) {
+                Some(after) => users
+                    .into_iter()
+                    .filter(|user| compare(user, after, &query.sort) == Ordering::Greater)
+                    .collect(),
+                None => Vec::new(),
+            };
+        }
+
+        let mut users: Vec<User> = users
+            .into_iter()
+            .skip(query.offset as usize)
+            .take(query.limit as usize + 1)
+            .cloned()
+            .collect();
+        let has_more = users.len() as i64 > query.limit;
+        users.truncate(query.limit as usize);
+
+        Ok(UserPage { users, total, has_more })
+    }
+
+    fn update(&self, id: i32, user: &User, versions: Option<&[i64]>) -> Result<Option<User>, DatabaseError> {
+        let mut state = self.state();
+        if !state.live_at(id, versions) {
+            return Ok(None);
+        }
+        state.check_email(&user.email, Some(id))?;
+
+        Ok(state.touch(id, |stored| {
+            stored.name = user.name.clone();
+            stored.email = user.email.clone();
+        }))
+    }
+
+    fn update_fields(&self, id: i32, changes: &UserChanges, version: i64) -> Result<Option<User>, DatabaseError> {
+        let mut state = self.state();
+        if !state.live_at(id, Some(&[version])) {
+            return Ok(None);
+        }
+        if let Some(email) = &changes.email {
+            state.check_email(email, Some(id))?;
+        }
+
+        Ok(state.touch(id, |stored| {
+            if let Some(name) = &changes.name {
+                stored.name = name.clone();
+            }
+            if let Some(email) = &changes.email {
+                stored.email = email.clone();
+            }
+        }))
+    }
+
+    fn delete(&self, id: i32, versions: Option<&[i64]>) -> Result<u64, DatabaseError> {
+        let mut state = self.state();
+        if !state.live_at(id, versions) {
+            return Ok(0);
+        }
+
+        state.touch(id, |stored| stored.deleted_at = Some(Utc::now()));
+        Ok(1)
+    }
+
+    fn restore(&self, id: i32) -> Result<Option<User>, DatabaseError> {
+        let mut state = self.state();
+        let email = match state.users.get(&id) {
+            Some(user) if user.deleted_at.is_some() => user.email.clone(),
+            _ => return Ok(None),
+        };
+        state.check_email(&email, Some(id))?;
+
+        Ok(state.touch(id, |stored| stored.deleted_at = None))
+    }
+
+    fn purge_deleted(&self, retention_days: i32) -> Result<u64, DatabaseError> {
+        // A cutoff before the earliest representable time leaves nothing to purge.
+        let cutoff = match Utc::now().checked_sub_signed(Duration::days(retention_days.into())) {
+            Some(cutoff) => cutoff,
+            None => return Ok(0),
+        };
+        let mut state = self.state();
+
+        let before = state.users.len();
+        state.users.retain(|_, user| user.deleted_at.is_none_or(|deleted_at| deleted_at >= cutoff));
+        Ok((before - state.users.len()) as u64)
+    }
+
+    fn ping(&self) -> Result<(), DatabaseError> {
+        self.check_reachable()
+    }
+
+    fn pending_migrations(&self) -> Result<usize, DatabaseError> {
+        self.check_reachable()?;
+        Ok(self.pending_migrations)
+    }
+}
+
+impl State {
+    // Whether `id` is a user that is not deleted and, given `versions`, is at
+    // one of them.
+    fn live_at(&self, id: i32, versions: Option<&[i64]>) -> bool {
+        self.users.get(&id).is_some_and(|user| {
+            user.deleted_at.is_none() && versions.is_none_or(|versions| versions.contains(&version(user)))
+        })
+    }
+
+    // The partial unique index on `users(email) WHERE deleted_at IS NULL`.
+    fn check_email(&self, email: &str, except: Option<i32>) -> Result<(), DatabaseError> {
+        let taken = self
+            .users
+            .values()
+            .any(|user| user.deleted_at.is_none() && user.email == email && user.id != except);
+        if taken {
+            let violation = ConstraintViolation::Unique {
+                constraint: Some("users_email_key".to_string()),
+                columns: vec!["email".to_string()],
+            };
+            return Err(DatabaseError::Constraint(violation, None));
+        }
+        Ok(())
+    }
+
+    // Applies `change` and then what the `users_touch` trigger does on UPDATE.
+    fn touch(&mut self, id: i32, change: impl FnOnce(&mut User)) -> Option<User> {
+        let user = self.users.get_mut(&id)?;
+        change(user);
+        user.updated_at = Some(Utc::now());
+        user.version = Some(version(user) + 1);
+        Some(user.clone())
+    }
+}
+
+fn version(user: &User) -> i64 {
+    user.version.unwrap_or_default()
+}
+
+fn matches_filters(user: &User, query: &UserQuery) -> bool {
+    let domain = user.email.rsplit_once('@').map(|(_, domain)| domain);
+
+    (query.include_deleted || user.deleted_at.is_none())
+        && query
+            .email_domain
+            .as_ref()
+            .is_none_or(|wanted| domain.is_some_and(|domain| domain.eq_ignore_ascii_case(wanted)))
+        && query
+            .name_contains
+            .as_ref()
+            .is_none_or(|part| user.name.to_lowercase().contains(&part.to_lowercase()))
+        && query.updated_since.is_none_or(|since| user.updated_at.is_some_and(|updated| updated >= since))
+}
+
+// Orders by the sort keys in turn, like the SQL ORDER BY.
+fn compare(a: &User, b: &User, sort: &[SortKey]) -> Ordering {
+    sort.iter()
+        .map(|key| {
+            let ordering = match key.field {
+                SortField::Id => a.id.cmp(&b.id),
+                SortField::Name => a.name.cmp(&b.name),
+                SortField::Email => a.email.cmp(&b.email),
+                SortField::UpdatedAt => a.updated_at.cmp(&b.updated_at),
+            };
+            if key.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
@@ -0,0 +1,73 @@
+use crate::database::PoolState;
+use crate::metrics;
+use crate::models::{User, UserChanges, UserPage, UserQuery};
+use crate::repositories::{AsyncUserRepository, RepositoryFuture};
+use std::time::Instant;
+
+/// Wraps another repository and records how long each call takes and
+/// whether it failed. Serves the blocking and async stores alike.
+pub struct MeteredUserRepository {
+    inner: Box<dyn AsyncUserRepository>,
+}
+
+impl MeteredUserRepository {
+    pub fn new(inner: Box<dyn AsyncUserRepository>) -> Self {
+        Self { inner }
+    }
+}
+
+fn timed<'a, T: 'a>(method: &'static str, call: RepositoryFuture<'a, T>) -> RepositoryFuture<'a, T> {
+    Box::pin(async move {
+        let started = Instant::now();
+        let result = call.await;
+        metrics::record_query(method, started.elapsed(), result.is_ok());
+        result
+    })
+}
+
+impl AsyncUserRepository for MeteredUserRepository {
+    fn create<'a>(&'a self, user: &'a User) -> RepositoryFuture<'a, User> {
+        timed("create", self.inner.create(user))
+    }
+
+    fn find_by_id(&self, id: i32, include_deleted: bool) -> RepositoryFuture<'_, Option<User>> {
+        timed("find_by_id", self.inner.find_by_id(id, include_deleted))
+    }
+
+    fn find_page<'a>(&'a self, query: &'a UserQuery) -> RepositoryFuture<'a, UserPage> {
+        timed("find_page", self.inner.find_page(query))
+    }
+
+    fn update<'a>(&'a self, id: i32, user: &'a User, versions: Option<&'a [i64]>) -> RepositoryFuture<'a, Option<User>> {
+        timed("update", self.inner.update(id, user, versions))
+    }
+
+    fn update_fields<'a>(&'a self, id: i32, changes: &'a UserChanges, version: i64) -> RepositoryFuture<'a, Option<User>> {
+        timed("update_fields", self.inner.update_fields(id, changes, version))
+    }
+
+    fn delete<'a>(&'a self, id: i32, versions: Option<&'a [i64]>) -> RepositoryFuture<'a, u64> {
+        timed("delete", self.inner.delete(id, versions))
+    }
+
+    fn restore(&self, id: i32) -> RepositoryFuture<'_, Option<User>> {
+        timed("restore", self.inner.restore(id))
+    }
+
+    fn purge_deleted(&self, retention_days: i32) -> RepositoryFuture<'_, u64> {
+        timed("purge_deleted", self.inner.purge_deleted(retention_days))
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        self.inner.pool_state()
+    }
+
+    // Health probes are left out of the query metrics.
+    fn ping(&self) -> RepositoryFuture<'_, ()> {
+        self.inner.ping()
+    }
+
+    fn pending_migrations(&self) -> RepositoryFuture<'_, usize> {
+        self.inner.pending_migrations()
+    }
+}
@@ -1,3 +1,24 @@
 pub mod user_repository;
+pub mod async_user_repository;
+// Stands in for Postgres in unit tests of the service and controller layers.
+#[cfg(test)]
+pub mod memory_user_repository;
+pub mod list_statement;
+pub mod metered_user_repository;
+pub mod postgres_user_repository;
+#[cfg(all(feature = "sqlite", not(feature = "async")))]
+pub mod sqlite_user_repository;
+#[cfg(feature = "async")]
+pub mod async_postgres_user_repository;
 
 pub use user_repository::UserRepository;
+pub use async_user_repository::{AsyncUserRepository, RepositoryFuture};
+#[cfg(test)]
+pub use memory_user_repository::InMemoryUserRepository;
+pub use metered_user_repository::MeteredUserRepository;
+#[cfg(not(feature = "async"))]
+pub use postgres_user_repository::PostgresUserRepository;
+#[cfg(all(feature = "sqlite", not(feature = "async")))]
+pub use sqlite_user_repository::SqliteUserRepository;
+#[cfg(feature = "async")]
+pub use async_postgres_user_repository::AsyncPostgresUserRepository;
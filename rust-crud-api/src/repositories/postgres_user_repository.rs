@@ -0,0 +1,231 @@
+use crate::models::{User, UserChanges};
+#[cfg(not(feature = "async"))]
+use crate::models::{UserPage, UserQuery};
+#[cfg(not(feature = "async"))]
+use crate::database::{Database, DatabaseError, PoolState};
+#[cfg(not(feature = "async"))]
+use crate::migrations;
+#[cfg(not(feature = "async"))]
+use crate::repositories::UserRepository;
+use crate::repositories::list_statement::{Dialect, ListParam, ListStatement};
+use postgres::types::ToSql;
+use postgres::Row;
+
+// Column list every query that returns users selects, in `user_from_row` order.
+pub(crate) const USER_COLUMNS: &str = "id, name, email, created_at, updated_at, version, deleted_at";
+
+// Condition on parameter `$n`, a nullable BIGINT[] of the versions the row
+// may be at; NULL accepts any version.
+pub(crate) fn versions_match(n: usize) -> String {
+    format!("(${0}::BIGINT[] IS NULL OR version = ANY(${0}))", n)
+}
+
+// The statements both Postgres repositories run, so that they cannot drift
+// apart. `ListStatement` and `update_fields_statement` build the rest.
+
+// $1 name, $2 email.
+pub(crate) fn insert_statement() -> String {
+    format!("INSERT INTO users (name, email) VALUES ($1, $2) RETURNING {}", USER_COLUMNS)
+}
+
+// $1 id, $2 whether a deleted user is found too.
+pub(crate) fn find_by_id_statement() -> String {
+    format!("SELECT {} FROM users WHERE id = $1 AND ($2 OR deleted_at IS NULL)", USER_COLUMNS)
+}
+
+// $1 name, $2 email, $3 id, $4 the versions `versions_match` accepts.
+pub(crate) fn update_statement() -> String {
+    format!(
+        "UPDATE users SET name = $1, email = $2 WHERE id = $3 AND deleted_at IS NULL AND {} RETURNING {}",
+        versions_match(4),
+        USER_COLUMNS
+    )
+}
+
+// Soft delete. $1 id, $2 the versions `versions_match` accepts.
+pub(crate) fn delete_statement() -> String {
+    format!(
+        "UPDATE users SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL AND {}",
+        versions_match(2)
+    )
+}
+
+// $1 id; matches only a deleted user.
+pub(crate) fn restore_statement() -> String {
+    format!(
+        "UPDATE users SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL RETURNING {}",
+        USER_COLUMNS
+    )
+}
+
+// $1 retention period in days.
+pub(crate) const PURGE_STATEMENT: &str = "DELETE FROM users WHERE deleted_at < now() - make_interval(days => $1)";
+
+pub(crate) const PING_STATEMENT: &str = "SELECT 1";
+
+pub(crate) fn user_from_row(row: &Row) -> User {
+    User {
+        id: Some(row.get(0)),
+        name: row.get(1),
+        email: row.get(2),
+        created_at: Some(row.get(3)),
+        updated_at: Some(row.get(4)),
+        version: Some(row.get(5)),
+        deleted_at: row.get(6),
+    }
+}
+
+#[cfg(not(feature = "async"))]
+pub struct PostgresUserRepository {
+    db: Database,
+}
+
+#[cfg(not(feature = "async"))]
+impl PostgresUserRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl UserRepository for PostgresUserRepository {
+    fn create(&self, user: &User) -> Result<User, DatabaseError> {
+        let row = self.db.get_client()?.query_one(
+            &insert_statement(),
+            &[&user.name, &user.email]
+        )?;
+        Ok(user_from_row(&row))
+    }
+
+    fn find_by_id(&self, id: i32, include_deleted: bool) -> Result<Option<User>, DatabaseError> {
+        let row = self.db.get_client()?.query_opt(
+            &find_by_id_statement(),
+            &[&id, &include_deleted],
+        )?;
+
+        Ok(row.as_ref().map(user_from_row))
+    }
+
+    fn find_page(&self, query: &UserQuery) -> Result<UserPage, DatabaseError> {
+        let statement = ListStatement::new(query, &LIST_DIALECT);
+        let params = list_params(&statement);
+        let mut client = self.db.get_client()?;
+
+        let total: i64 = client
+            .query_one(&statement.count, &params[..statement.filter_params])?
+            .get(0);
+        let users = client
+            .query(&statement.select, &params)?
+            .iter()
+            .map(user_from_row)
+            .collect();
+
+        Ok(statement.page(users, total))
+    }
+
+    // The version check and the write are one statement, so no other writer
+    // can slip in between.
+    fn update(&self, id: i32, user: &User, versions: Option<&[i64]>) -> Result<Option<User>, DatabaseError> {
+        let row = self.db.get_client()?.query_opt(
+            &update_statement(),
+            &[&user.name, &user.email, &id, &versions],
+        )?;
+        Ok(row.as_ref().map(user_from_row))
+    }
+
+    fn update_fields(&self, id: i32, changes: &UserChanges, version: i64) -> Result<Option<User>, DatabaseError> {
+        let (sql, params) = update_fields_statement(&id, changes, &version);
+        let row = self.db.get_client()?.query_opt(&sql, &params)?;
+        Ok(row.as_ref().map(user_from_row))
+    }
+
+    fn delete(&self, id: i32, versions: Option<&[i64]>) -> Result<u64, DatabaseError> {
+        let rows_affected = self.db.get_client()?.execute(
+            &delete_statement(),
+            &[&id, &versions]
+        )?;
+        Ok(rows_affected)
+    }
+
+    fn restore(&self, id: i32) -> Result<Option<User>, DatabaseError> {
+        let row = self.db.get_client()?.query_opt(
+            &restore_statement(),
+            &[&id],
+        )?;
+        Ok(row.as_ref().map(user_from_row))
+    }
+
+    fn purge_deleted(&self, retention_days: i32) -> Result<u64, DatabaseError> {
+        let rows_affected = self.db.get_client()?.execute(
+            PURGE_STATEMENT,
+            &[&retention_days],
+        )?;
+        Ok(rows_affected)
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        Some(self.db.state())
+    }
+
+    fn ping(&self) -> Result<(), DatabaseError> {
+        self.db.get_client()?.batch_execute(PING_STATEMENT)?;
+        Ok(())
+    }
+
+    fn pending_migrations(&self) -> Result<usize, DatabaseError> {
+        let rows = self.db.get_client()?.query(migrations::APPLIED_VERSIONS, &[])?;
+        let applied: Vec<i64> = rows.iter().map(|row| row.get(0)).collect();
+        Ok(migrations::pending(&applied))
+    }
+}
+
+// UPDATE for only the changed columns; `changes` must not be empty.
+pub(crate) fn update_fields_statement<'a>(
+    id: &'a i32,
+    changes: &'a UserChanges,
+    version: &'a i64,
+) -> (String, Vec<&'a (dyn ToSql + Sync)>) {
+    let mut assignments = Vec::new();
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+
+    if let Some(name) = &changes.name {
+        params.push(name);
+        assignments.push(format!("name = ${}", params.len()));
+    }
+    if let Some(email) = &changes.email {
+        params.push(email);
+        assignments.push(format!("email = ${}", params.len()));
+    }
+    params.push(id);
+    params.push(version);
+
+    let sql = format!(
+        "UPDATE users SET {} WHERE id = ${} AND deleted_at IS NULL AND version = ${} RETURNING {}",
+        assignments.join(", "),
+        params.len() - 1,
+        params.len(),
+        USER_COLUMNS
+    );
+    (sql, params)
+}
+
+// The list SQL in Postgres syntax. strpos rather than LIKE so `%` and `_` in
+// the name match literally.
+pub(crate) const LIST_DIALECT: Dialect = Dialect {
+    placeholder: |n| format!("${}", n),
+    email_domain: |domain| format!("lower(substring(email from '@([^@]*)$')) = lower({})", domain),
+    name_contains: |name| format!("strpos(lower(name), lower({})) > 0", name),
+};
+
+pub(crate) fn list_params(statement: &ListStatement) -> Vec<&(dyn ToSql + Sync)> {
+    statement
+        .params
+        .iter()
+        .map(|param| match param {
+            ListParam::Text(text) => text as &(dyn ToSql + Sync),
+            ListParam::Time(time) => time,
+            ListParam::Id(id) => id,
+            ListParam::Count(count) => count,
+        })
+        .collect()
+}
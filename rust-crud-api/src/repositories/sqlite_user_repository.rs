@@ -0,0 +1,347 @@
+use crate::database::{DatabaseError, PoolState};
+use crate::models::{User, UserChanges, UserPage, UserQuery};
+use crate::repositories::list_statement::{Dialect, ListParam, ListStatement};
+use crate::repositories::postgres_user_repository::USER_COLUMNS;
+use crate::repositories::UserRepository;
+use crate::sqlite_database::SqliteDatabase;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use rusqlite::types::Type;
+use rusqlite::{params_from_iter, OptionalExtension, Row, ToSql};
+
+type Params = Vec<Box<dyn ToSql>>;
+
+// What the Postgres `users_touch` trigger does, spelled out in each UPDATE
+// because RETURNING would not see a trigger's changes here. `?1` is now.
+const TOUCH: &str = "updated_at = ?1, version = version + 1";
+
+// The list SQL in SQLite syntax. The domain is the part of the email after
+// its last `@`, which a domain never contains.
+const LIST_DIALECT: Dialect = Dialect {
+    placeholder: |n| format!("?{}", n),
+    email_domain: |domain| {
+        format!(
+            "instr({0}, '@') = 0 AND length(email) > length({0}) \
+             AND substr(email, -length({0}) - 1, 1) = '@' AND lower(substr(email, -length({0}))) = lower({0})",
+            domain
+        )
+    },
+    name_contains: |name| format!("instr(lower(name), lower({})) > 0", name),
+};
+
+/// `UserRepository` on SQLite, behaving like the Postgres one: the partial
+/// unique index keeps emails unique among users that are not deleted. Note
+/// that SQLite's `lower()` only folds ASCII letters in the filters.
+pub struct SqliteUserRepository {
+    db: SqliteDatabase,
+}
+
+impl SqliteUserRepository {
+    pub fn new(db: SqliteDatabase) -> Self {
+        Self { db }
+    }
+
+    fn query_user(&self, sql: &str, params: Params) -> Result<Option<User>, DatabaseError> {
+        Ok(self
+            .db
+            .get_client()?
+            .query_row(sql, params_from_iter(params), user_from_row)
+            .optional()?)
+    }
+}
+
+impl UserRepository for SqliteUserRepository {
+    fn create(&self, user: &User) -> Result<User, DatabaseError> {
+        let user = self.db.get_client()?.query_row(
+            &format!(
+                "INSERT INTO users (name, email, created_at, updated_at) VALUES (?1, ?2, ?3, ?3) RETURNING {}",
+                USER_COLUMNS
+            ),
+            (&user.name, &user.email, timestamp(Utc::now())),
+            user_from_row,
+        )?;
+        Ok(user)
+    }
+
+    fn find_by_id(&self, id: i32, include_deleted: bool) -> Result<Option<User>, DatabaseError> {
+        self.query_user(
+            &format!("SELECT {} FROM users WHERE id = ?1 AND (?2 OR deleted_at IS NULL)", USER_COLUMNS),
+            vec![Box::new(id), Box::new(include_deleted)],
+        )
+    }
+
+    fn find_page(&self, query: &UserQuery) -> Result<UserPage, DatabaseError> {
+        let statement = ListStatement::new(query, &LIST_DIALECT);
+        let params: Params = statement
+            .params
+            .iter()
+            .map(|param| -> Box<dyn ToSql> {
+                match param {
+                    ListParam::Text(text) => Box::new(text.clone()),
+                    ListParam::Time(time) => Box::new(timestamp(*time)),
+                    ListParam::Id(id) => Box::new(*id),
+                    ListParam::Count(count) => Box::new(*count),
+                }
+            })
+            .collect();
+
+        let client = self.db.get_client()?;
+        let total: i64 = client.query_row(
+            &statement.count,
+            params_from_iter(&params[..statement.filter_params]),
+            |row| row.get(0),
+        )?;
+        let users = client
+            .prepare(&statement.select)?
+            .query_map(params_from_iter(&params), user_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(statement.page(users, total))
+    }
+
+    fn update(&self, id: i32, user: &User, versions: Option<&[i64]>) -> Result<Option<User>, DatabaseError> {
+        let mut params: Params = vec![
+            Box::new(timestamp(Utc::now())),
+            Box::new(user.name.clone()),
+            Box::new(user.email.clone()),
+            Box::new(id),
+        ];
+        let versions = versions_match(versions, &mut params);
+        self.query_user(
+            &format!(
+                "UPDATE users SET name = ?2, email = ?3, {} WHERE id = ?4 AND deleted_at IS NULL AND {} RETURNING {}",
+                TOUCH, versions, USER_COLUMNS
+            ),
+            params,
+        )
+    }
+
+    fn update_fields(&self, id: i32, changes: &UserChanges, version: i64) -> Result<Option<User>, DatabaseError> {
+        let mut params: Params = vec![Box::new(timestamp(Utc::now()))];
+        let mut assignments = vec![TOUCH.to_string()];
+
+        if let Some(name) = &changes.name {
+            params.push(Box::new(name.clone()));
+            assignments.push(format!("name = ?{}", params.len()));
+        }
+        if let Some(email) = &changes.email {
+            params.push(Box::new(email.clone()));
+            assignments.push(format!("email = ?{}", params.len()));
+        }
+        params.push(Box::new(id));
+        params.push(Box::new(version));
+
+        self.query_user(
+            &format!(
+                "UPDATE users SET {} WHERE id = ?{} AND deleted_at IS NULL AND version = ?{} RETURNING {}",
+                assignments.join(", "),
+                params.len() - 1,
+                params.len(),
+                USER_COLUMNS
+            ),
+            params,
+        )
+    }
+
+    fn delete(&self, id: i32, versions: Option<&[i64]>) -> Result<u64, DatabaseError> {
+        let mut params: Params = vec![Box::new(timestamp(Utc::now())), Box::new(id)];
+        let versions = versions_match(versions, &mut params);
+        let rows_affected = self.db.get_client()?.execute(
+            &format!(
+                "UPDATE users SET deleted_at = ?1, {} WHERE id = ?2 AND deleted_at IS NULL AND {}",
+                TOUCH, versions
+            ),
+            params_from_iter(params),
+        )?;
+        Ok(rows_affected as u64)
+    }
+
+    fn restore(&self, id: i32) -> Result<Option<User>, DatabaseError> {
+        self.query_user(
+            &format!(
+                "UPDATE users SET deleted_at = NULL, {} WHERE id = ?2 AND deleted_at IS NOT NULL RETURNING {}",
+                TOUCH, USER_COLUMNS
+            ),
+            vec![Box::new(timestamp(Utc::now())), Box::new(id)],
+        )
+    }
+
+    fn purge_deleted(&self, retention_days: i32) -> Result<u64, DatabaseError> {
+        // A cutoff before the earliest representable time leaves nothing to purge.
+        let cutoff = match Utc::now().checked_sub_signed(Duration::days(retention_days.into())) {
+            Some(cutoff) => cutoff,
+            None => return Ok(0),
+        };
+        let rows_affected = self
+            .db
+            .get_client()?
+            .execute("DELETE FROM users WHERE deleted_at < ?1", [timestamp(cutoff)])?;
+        Ok(rows_affected as u64)
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        Some(self.db.state())
+    }
+
+    fn ping(&self) -> Result<(), DatabaseError> {
+        self.db.get_client()?.execute_batch("SELECT 1")?;
+        Ok(())
+    }
+
+    fn pending_migrations(&self) -> Result<usize, DatabaseError> {
+        self.db.pending_schema_steps()
+    }
+}
+
+// `version IN (...)` over parameters appended to `params`; None accepts any
+// version and an empty list none.
+fn versions_match(versions: Option<&[i64]>, params: &mut Params) -> String {
+    let versions = match versions {
+        Some(versions) => versions,
+        None => return "1".to_string(),
+    };
+
+    let placeholders: Vec<String> = versions
+        .iter()
+        .map(|version| {
+            params.push(Box::new(*version));
+            format!("?{}", params.len())
+        })
+        .collect();
+    format!("version IN ({})", placeholders.join(", "))
+}
+
+// Fixed width, so that text order is time order.
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn parse_timestamp(row: &Row, index: usize) -> rusqlite::Result<DateTime<Utc>> {
+    let text: String = row.get(index)?;
+    DateTime::parse_from_rfc3339(&text)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    let deleted_at = match row.get::<_, Option<String>>(6)? {
+        Some(_) => Some(parse_timestamp(row, 6)?),
+        None => None,
+    };
+
+    Ok(User {
+        id: Some(row.get(0)?),
+        name: row.get(1)?,
+        email: row.get(2)?,
+        created_at: Some(parse_timestamp(row, 3)?),
+        updated_at: Some(parse_timestamp(row, 4)?),
+        version: Some(row.get(5)?),
+        deleted_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PoolConfig;
+    use crate::database::ConstraintViolation;
+    use crate::http::query::Query;
+    use serde_json::json;
+
+    // One connection, since every connection to `:memory:` opens a database of its own.
+    fn repository() -> SqliteUserRepository {
+        let pool_config = PoolConfig {
+            min_idle: 1,
+            max_size: 1,
+            idle_timeout: None,
+            connection_timeout: std::time::Duration::from_secs(5),
+        };
+        SqliteUserRepository::new(SqliteDatabase::open(":memory:", &pool_config).unwrap())
+    }
+
+    fn user(name: &str, email: &str) -> User {
+        serde_json::from_value(json!({ "id": null, "name": name, "email": email })).unwrap()
+    }
+
+    fn emails(repository: &SqliteUserRepository, query: &str) -> Vec<String> {
+        let query = UserQuery::from_query(&Query::parse(query)).unwrap();
+        repository.find_page(&query).unwrap().users.into_iter().map(|user| user.email).collect()
+    }
+
+    #[test]
+    fn a_taken_email_is_a_unique_violation_on_the_email_column() {
+        let repository = repository();
+        repository.create(&user("Ada", "ada@example.com")).unwrap();
+
+        match repository.create(&user("Other Ada", "ada@example.com")) {
+            Err(DatabaseError::Constraint(ConstraintViolation::Unique { columns, .. }, _)) => {
+                assert_eq!(columns, vec!["email"]);
+            }
+            other => panic!("expected a unique violation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_deleted_user_is_hidden_frees_its_email_and_can_be_restored() {
+        let repository = repository();
+        let id = repository.create(&user("Ada", "ada@example.com")).unwrap().id.unwrap();
+
+        assert_eq!(repository.delete(id, None).unwrap(), 1);
+        assert_eq!(repository.delete(id, None).unwrap(), 0);
+        assert!(repository.find_by_id(id, false).unwrap().is_none());
+        let deleted = repository.find_by_id(id, true).unwrap().unwrap();
+        assert!(deleted.deleted_at.is_some());
+        assert_eq!(deleted.version, Some(2));
+
+        let other = repository.create(&user("Other Ada", "ada@example.com")).unwrap();
+        assert!(matches!(
+            repository.restore(id),
+            Err(DatabaseError::Constraint(ConstraintViolation::Unique { .. }, _))
+        ));
+        repository.delete(other.id.unwrap(), None).unwrap();
+
+        let restored = repository.restore(id).unwrap().unwrap();
+        assert_eq!(restored.deleted_at, None);
+        assert_eq!(restored.version, Some(3));
+        assert!(repository.restore(id).unwrap().is_none());
+    }
+
+    #[test]
+    fn writes_only_apply_at_one_of_the_expected_versions() {
+        let repository = repository();
+        let id = repository.create(&user("Ada", "ada@example.com")).unwrap().id.unwrap();
+        let changed = user("Ada L", "ada@example.com");
+
+        assert!(repository.update(id, &changed, Some(&[])).unwrap().is_none());
+        assert!(repository.update(id, &changed, Some(&[2, 3])).unwrap().is_none());
+        let updated = repository.update(id, &changed, Some(&[1, 3])).unwrap().unwrap();
+        assert_eq!((updated.name.as_str(), updated.version), ("Ada L", Some(2)));
+
+        assert_eq!(repository.delete(id, Some(&[1])).unwrap(), 0);
+        assert_eq!(repository.delete(id, Some(&[2])).unwrap(), 1);
+    }
+
+    #[test]
+    fn the_email_domain_filter_matches_the_part_after_the_last_at_sign() {
+        let repository = repository();
+        for email in ["ada@example.com", "bob@EXAMPLE.com", "cy@mail.example.com", "dee@example.com.au"] {
+            repository.create(&user("Someone", email)).unwrap();
+        }
+
+        assert_eq!(emails(&repository, "email_domain=example.com"), vec!["ada@example.com", "bob@EXAMPLE.com"]);
+        assert_eq!(emails(&repository, "email_domain=mail.example.com"), vec!["cy@mail.example.com"]);
+        assert_eq!(emails(&repository, "email_domain=com"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn pages_follow_the_sort_order_from_the_after_cursor() {
+        let repository = repository();
+        for email in ["a@example.com", "b@example.com", "c@example.com"] {
+            repository.create(&user("Someone", email)).unwrap();
+        }
+
+        let query = UserQuery::from_query(&Query::parse("sort=-id&limit=1&after=3")).unwrap();
+        let page = repository.find_page(&query).unwrap();
+        assert_eq!(page.users[0].email, "b@example.com");
+        assert_eq!((page.total, page.has_more), (3, true));
+    }
+}
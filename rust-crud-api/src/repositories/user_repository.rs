@@ -1,67 +1,45 @@
-use crate::models::User;
-use crate::database::Database;
-use postgres::Error as PostgresError;
+use crate::database::{DatabaseError, PoolState};
+use crate::models::{User, UserChanges, UserPage, UserQuery};
 
-pub struct UserRepository {
-    db: Database,
-}
+/// Storage for users. Every implementation must behave like the Postgres
+/// schema: emails are unique among users that are not deleted, each write
+/// bumps `version` and `updated_at`, and deleted users stay hidden unless
+/// asked for until they are purged.
+pub trait UserRepository: Send + Sync {
+    fn create(&self, user: &User) -> Result<User, DatabaseError>;
 
-impl UserRepository {
-    pub fn new(db: Database) -> Self {
-        Self { db }
-    }
+    fn find_by_id(&self, id: i32, include_deleted: bool) -> Result<Option<User>, DatabaseError>;
 
-    pub fn create(&mut self, user: &User) -> Result<(), PostgresError> {
-        self.db.get_client().execute(
-            "INSERT INTO users (name, email) VALUES ($1, $2)",
-            &[&user.name, &user.email]
-        )?;
-        Ok(())
-    }
+    fn find_page(&self, query: &UserQuery) -> Result<UserPage, DatabaseError>;
 
-    pub fn find_by_id(&mut self, id: i32) -> Result<Option<User>, PostgresError> {
-        let rows = self.db.get_client().query("SELECT id, name, email FROM users WHERE id = $1", &[&id])?;
-        
-        if rows.is_empty() {
-            Ok(None)
-        } else {
-            let row = &rows[0];
-            let user = User::with_id(
-                row.get(0),
-                row.get(1),
-                row.get(2),
-            );
-            Ok(Some(user))
-        }
-    }
+    // With `versions`, only a user at one of those versions is updated.
+    fn update(&self, id: i32, user: &User, versions: Option<&[i64]>) -> Result<Option<User>, DatabaseError>;
 
-    pub fn find_all(&mut self) -> Result<Vec<User>, PostgresError> {
-        let mut users = Vec::new();
-        
-        for row in self.db.get_client().query("SELECT id, name, email FROM users", &[])? {
-            users.push(User::with_id(
-                row.get(0),
-                row.get(1),
-                row.get(2),
-            ));
-        }
-        
-        Ok(users)
+    // Only updates the user if it is still at `version`, the one `changes`
+    // were computed from.
+    fn update_fields(&self, id: i32, changes: &UserChanges, version: i64) -> Result<Option<User>, DatabaseError>;
+
+    // Soft delete: the user stays, hidden, until `purge_deleted` removes it.
+    fn delete(&self, id: i32, versions: Option<&[i64]>) -> Result<u64, DatabaseError>;
+
+    // None unless the user exists and is deleted.
+    fn restore(&self, id: i32) -> Result<Option<User>, DatabaseError>;
+
+    // Hard-deletes users that were deleted more than `retention_days` ago.
+    fn purge_deleted(&self, retention_days: i32) -> Result<u64, DatabaseError>;
+
+    // None for stores that do not pool connections.
+    fn pool_state(&self) -> Option<PoolState> {
+        None
     }
 
-    pub fn update(&mut self, id: i32, user: &User) -> Result<u64, PostgresError> {
-        let rows_affected = self.db.get_client().execute(
-            "UPDATE users SET name = $1, email = $2 WHERE id = $3",
-            &[&user.name, &user.email, &id],
-        )?;
-        Ok(rows_affected)
+    // A cheap round trip to the store, for readiness checks.
+    fn ping(&self) -> Result<(), DatabaseError> {
+        Ok(())
     }
 
-    pub fn delete(&mut self, id: i32) -> Result<u64, PostgresError> {
-        let rows_affected = self.db.get_client().execute(
-            "DELETE FROM users WHERE id = $1",
-            &[&id]
-        )?;
-        Ok(rows_affected)
+    // Schema changes this build expects that the store does not have yet.
+    fn pending_migrations(&self) -> Result<usize, DatabaseError> {
+        Ok(0)
     }
 }
@@ -0,0 +1,76 @@
+use crate::controllers::{DocsController, HealthController, MetricsController, UserController};
+use crate::http::{Method, Request, Response, Router, TrailingSlash};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Handlers take the request by value so their futures can outlive the
+/// connection's borrow; the blocking server runs them with `block_on`.
+pub type Handler = Box<dyn Fn(Request) -> Pin<Box<dyn Future<Output = Response> + Send>> + Send + Sync>;
+
+pub fn build_router(
+    user_controller: Arc<UserController>,
+    health_controller: Arc<HealthController>,
+    metrics_controller: Arc<MetricsController>,
+    trailing_slash: TrailingSlash,
+    docs_page: bool,
+) -> Router<Handler> {
+    let docs_controller = Arc::new(DocsController::new());
+    let router = Router::new()
+        .trailing_slash(trailing_slash)
+        .nest("/users", user_routes(&user_controller))
+        .route(Method::Get, "/healthz", handler(&health_controller, |c, r| async move { c.healthz(&r) }))
+        .route(Method::Get, "/readyz", handler(&health_controller, |c, r| async move { c.readyz(&r).await }))
+        .route(Method::Get, "/metrics", handler(&metrics_controller, |c, r| async move { c.metrics(&r) }))
+        .route(Method::Get, "/openapi.json", handler(&docs_controller, |c, r| async move { c.openapi(&r) }));
+    #[cfg(feature = "docs-ui")]
+    let router = if docs_page {
+        router
+            .route(Method::Get, "/docs", handler(&docs_controller, |c, r| async move { c.docs(&r) }))
+            .route(
+                Method::Get,
+                "/docs/swagger-ui.css",
+                handler(&docs_controller, |c, r| async move { c.swagger_ui_css(&r) }),
+            )
+            .route(
+                Method::Get,
+                "/docs/swagger-ui-bundle.js",
+                handler(&docs_controller, |c, r| async move { c.swagger_ui_bundle(&r) }),
+            )
+            .route(
+                Method::Get,
+                "/docs/swagger-ui-bundle.js.LICENSE.txt",
+                handler(&docs_controller, |c, r| async move { c.swagger_ui_licenses(&r) }),
+            )
+    } else {
+        router
+    };
+    #[cfg(not(feature = "docs-ui"))]
+    debug_assert!(!docs_page, "server.docs_page needs the docs-ui feature");
+
+    // The document describes the routes above, so it is built last.
+    docs_controller.publish(router.routes());
+    router
+}
+
+fn user_routes(controller: &Arc<UserController>) -> Router<Handler> {
+    Router::new()
+        .route(Method::Post, "/", handler(controller, |c, r| async move { c.create_user(&r).await }))
+        .route(Method::Get, "/", handler(controller, |c, r| async move { c.list_users(&r).await }))
+        .route(Method::Get, "/:id", handler(controller, |c, r| async move { c.get_user(&r).await }))
+        .route(Method::Put, "/:id", handler(controller, |c, r| async move { c.update_user(&r).await }))
+        .route(Method::Patch, "/:id", handler(controller, |c, r| async move { c.patch_user(&r).await }))
+        .route(Method::Delete, "/:id", handler(controller, |c, r| async move { c.delete_user(&r).await }))
+        .route(Method::Post, "/:id/restore", handler(controller, |c, r| async move { c.restore_user(&r).await }))
+        .route(Method::Post, "/purge", handler(controller, |c, r| async move { c.purge_users(&r).await }))
+}
+
+fn handler<C, F, Fut>(controller: &Arc<C>, action: F) -> Handler
+where
+    C: Send + Sync + 'static,
+    F: Fn(Arc<C>, Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    let controller = Arc::clone(controller);
+    Box::new(move |request| Box::pin(action(Arc::clone(&controller), request)))
+}
@@ -1,70 +1,438 @@
-use crate::controllers::{UserController};
-use crate::controllers::user_controller::NOT_FOUND;
+use crate::http::{Method, ParseError, Problem, Query, Request, Response, StatusCode};
+use crate::logging;
+use crate::metrics;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+// The blocking server; the async one only shares the responses and `Exchange`.
+#[cfg(not(feature = "async"))]
+use crate::config::{AdminConfig, ServerConfig};
+#[cfg(not(feature = "async"))]
+use crate::controllers::{HealthController, MetricsController, UserController};
+#[cfg(not(feature = "async"))]
+use crate::http::{RequestParser, RouteMatch, Router};
+#[cfg(not(feature = "async"))]
+use crate::routes::{self, Handler};
+#[cfg(not(feature = "async"))]
 use crate::services::UserService;
-use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write};
-use std::sync::{Arc, Mutex};
+#[cfg(not(feature = "async"))]
+use crate::shutdown::{self, Shutdown};
+#[cfg(not(feature = "async"))]
+use crate::thread_pool::ThreadPool;
+#[cfg(not(feature = "async"))]
+use crate::utils::block_on;
+#[cfg(not(feature = "async"))]
+use std::io::{self, Read, Write};
+#[cfg(not(feature = "async"))]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener, TcpStream};
+#[cfg(not(feature = "async"))]
+use std::sync::Arc;
 
+#[cfg(not(feature = "async"))]
+enum ReadError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+#[cfg(not(feature = "async"))]
+// How long the accept loop will spend telling a client the server is busy.
+const REJECT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+// How often idle keep-alive connections check for a shutdown.
+pub(crate) const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+// Longest `X-Request-Id` from a client that is kept rather than replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+// Sent when a client holds its body back until told to go ahead.
+pub(crate) const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+#[cfg(not(feature = "async"))]
 pub struct Server {
     listener: TcpListener,
-    user_controller: UserController,
+    pool: ThreadPool<TcpStream>,
+    shutdown: Shutdown,
+    shutdown_timeout: Duration,
+}
+
+#[cfg(not(feature = "async"))]
+// State shared by every worker thread.
+struct ConnectionHandler {
+    config: ServerConfig,
+    router: Router<Handler>,
+    shutdown: Shutdown,
 }
 
+#[cfg(not(feature = "async"))]
 impl Server {
-    pub fn new(address: &str, user_service: Arc<Mutex<UserService>>) -> Result<Self, Box<dyn std::error::Error>> {
-        let listener = TcpListener::bind(address)?;
-        let user_controller = UserController::new(user_service);
+    pub fn new(
+        config: ServerConfig,
+        admin: AdminConfig,
+        user_service: Arc<UserService>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind(&config.address)?;
+        // Readiness fails as soon as a shutdown starts, so load balancers stop
+        // sending new requests while the in-flight ones drain.
+        let shutdown = Shutdown::new();
+        let health_controller = Arc::new(HealthController::new(Arc::clone(&user_service), shutdown.clone()));
+        let metrics_service = Arc::clone(&user_service);
+        let metrics_controller = Arc::new(MetricsController::new(move || metrics_service.pool_state()));
+        let user_controller = Arc::new(UserController::new(user_service, admin));
+        let router = routes::build_router(
+            user_controller,
+            health_controller,
+            metrics_controller,
+            config.trailing_slash,
+            config.docs_page,
+        );
+
+        let worker_threads = config.worker_threads;
+        let queue_depth = config.queue_depth;
+        let shutdown_timeout = config.shutdown_timeout;
+        let handler = ConnectionHandler {
+            config,
+            router,
+            shutdown: shutdown.clone(),
+        };
+        let pool = ThreadPool::new(worker_threads, queue_depth, move |stream| handler.handle_client(stream));
         
         Ok(Server {
             listener,
-            user_controller,
+            pool,
+            shutdown,
+            shutdown_timeout,
         })
     }
 
-    pub fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Server berjalan di {}", self.listener.local_addr()?);
+    /// Serves connections until SIGTERM or SIGINT, then waits for in-flight
+    /// requests to finish. Fails if connections are still busy when the
+    /// shutdown timeout runs out.
+    pub fn run(self) -> Result<(), Box<dyn std::error::Error>> {
+        let local_addr = self.listener.local_addr()?;
+        logging::info!("server started", address = local_addr);
+
+        // `accept` has no timeout, so the signal thread wakes the loop up by
+        // connecting to the listener itself.
+        let shutdown = self.shutdown.clone();
+        let wake_addr = loopback_addr(local_addr);
+        shutdown::on_signal(move || {
+            shutdown.request();
+            let _ = TcpStream::connect(wake_addr);
+        })?;
 
         for stream in self.listener.incoming() {
+            if self.shutdown.is_requested() {
+                break;
+            }
+
             match stream {
                 Ok(stream) => {
-                    self.handle_client(stream);
+                    if let Err(stream) = self.pool.try_execute(stream) {
+                        reject_busy(stream);
+                    }
                 }
                 Err(e) => {
-                    eprintln!("Gagal menerima koneksi: {}", e);
+                    logging::warn!("failed to accept connection", error = e.to_string());
                 }
             }
         }
+
+        drop(self.listener);
+        logging::info!("stopped accepting connections, draining in-flight requests");
+
+        let busy = self.pool.shutdown(self.shutdown_timeout);
+        if busy > 0 {
+            return Err(format!("{} connection(s) still busy after the shutdown timeout", busy).into());
+        }
         Ok(())
     }
+}
 
+#[cfg(not(feature = "async"))]
+impl ConnectionHandler {
     fn handle_client(&self, mut stream: TcpStream) {
-        let mut buffer = [0; 1024];
-        let mut request = String::new();
+        if let Err(e) = stream.set_write_timeout(Some(self.config.write_timeout)) {
+            logging::warn!("failed to set connection timeouts", error = e.to_string());
+            return;
+        }
+
+        let _connection = metrics::connection_opened();
+        let client = stream.peer_addr().ok();
+        let mut parser = RequestParser::new(self.config.parser_limits());
+
+        // Requests are answered one at a time in arrival order, which is all
+        // pipelining needs: anything already buffered is parsed before reading.
+        loop {
+            let mut head_only = false;
+            let (exchange, response, keep_alive) = match read_request(&mut stream, &mut parser, &self.config, &self.shutdown) {
+                Ok(Some(request)) => {
+                    head_only = request.method == Method::Head;
+                    let mut exchange = Exchange::start(Some(&request));
+                    // Handlers take the request by value, so decide this first.
+                    let wants_keep_alive = request.wants_keep_alive();
+                    let response = self.route_request(request, &mut exchange);
+                    // Once shutdown starts, finish this response and hang up.
+                    let keep_alive = self.config.keep_alive_timeout.is_some()
+                        && wants_keep_alive
+                        && !self.shutdown.is_requested();
+                    (exchange, response, keep_alive)
+                }
+                Ok(None) => return,
+                Err(ReadError::Parse(e)) => {
+                    // The framing of anything after a bad request is unknown, so close.
+                    (Exchange::start(None), parse_error_response(&e), false)
+                }
+                Err(ReadError::Io(e)) if is_timeout(&e) => {
+                    (Exchange::start(None), request_timeout(), false)
+                }
+                Err(ReadError::Io(e)) => {
+                    logging::debug!("unable to read stream", client = client, error = e.to_string());
+                    return;
+                }
+            };
+
+            // HEAD gets the GET headers, Content-Length included, but no body.
+            let response = response.with_header("X-Request-Id", exchange.request_id.clone());
+            let written = stream.write_all(&response.to_bytes(keep_alive, !head_only));
+            let sent = if written.is_ok() && !head_only { response.body.len() } else { 0 };
+            exchange.finish(&response, sent, client);
+
+            if let Err(e) = written {
+                logging::warn!("failed to write response", client = client, error = e.to_string());
+                return;
+            }
+
+            if !keep_alive {
+                return;
+            }
+        }
+    }
+
+    fn route_request(&self, mut request: Request, exchange: &mut Exchange) -> Response {
+        match self.router.find(request.method, &request.path) {
+            RouteMatch::Found(handler, params, route) => {
+                request.params = params;
+                exchange.route = Some(route.to_string());
+                // This worker thread is the handler's to block.
+                block_on(handler(request))
+            }
+            RouteMatch::MethodNotAllowed(allowed) => method_not_allowed(&allowed),
+            RouteMatch::Redirect(location) => redirect(&location, &request.query),
+            RouteMatch::NotFound => not_found(&request.path),
+        }
+    }
+}
 
-        match stream.read(&mut buffer) {
-            Ok(size) => {
-                request.push_str(String::from_utf8_lossy(&buffer[..size]).as_ref());
+#[cfg(not(feature = "async"))]
+// Reads from the stream until the parser has a complete request. Returns
+// `Ok(None)` if the client closed the connection between requests, or it
+// stayed idle past the keep-alive timeout or into a shutdown.
+fn read_request(stream: &mut TcpStream, parser: &mut RequestParser, config: &ServerConfig, shutdown: &Shutdown) -> Result<Option<Request>, ReadError> {
+    let mut buffer = [0; 4096];
 
-                let (status_line, content) = self.route_request(&request);
+    loop {
+        if let Some(request) = parser.parse().map_err(ReadError::Parse)? {
+            return Ok(Some(request));
+        }
+        if parser.take_continue() {
+            stream.write_all(CONTINUE).map_err(ReadError::Io)?;
+        }
 
-                if let Err(e) = stream.write_all(format!("{}{}", status_line, content).as_bytes()) {
-                    eprintln!("Failed to write response: {}", e);
+        if parser.is_empty() {
+            if let Some(idle) = config.keep_alive_timeout {
+                if !wait_for_request(stream, idle, shutdown)? {
+                    return Ok(None);
                 }
             }
-            Err(e) => {
-                eprintln!("Unable to read stream: {}", e);
+        }
+
+        stream.set_read_timeout(Some(config.read_timeout)).map_err(ReadError::Io)?;
+
+        let size = stream.read(&mut buffer).map_err(ReadError::Io)?;
+        if size == 0 {
+            if parser.is_empty() {
+                return Ok(None);
+            }
+            return Err(ReadError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed mid-request",
+            )));
+        }
+        parser.feed(&buffer[..size]);
+    }
+}
+
+#[cfg(not(feature = "async"))]
+// Waits in short slices for the next request on an idle connection so that a
+// shutdown does not have to sit out the whole keep-alive timeout. Returns
+// false if the connection should be closed instead.
+fn wait_for_request(stream: &TcpStream, idle: Duration, shutdown: &Shutdown) -> Result<bool, ReadError> {
+    // A timeout too long to add to the clock never runs out.
+    let deadline = Instant::now().checked_add(idle);
+    let mut probe = [0; 1];
+
+    loop {
+        let remaining = deadline.map_or(IDLE_POLL_INTERVAL, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        });
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+
+        stream
+            .set_read_timeout(Some(remaining.min(IDLE_POLL_INTERVAL)))
+            .map_err(ReadError::Io)?;
+        match stream.peek(&mut probe) {
+            // Data or end of stream; either way the caller's read handles it.
+            Ok(_) => return Ok(true),
+            Err(e) if is_timeout(&e) => {
+                if shutdown.is_requested() {
+                    return Ok(false);
+                }
             }
+            Err(e) => return Err(ReadError::Io(e)),
+        }
+    }
+}
+
+#[cfg(not(feature = "async"))]
+fn reject_busy(mut stream: TcpStream) {
+    let response = service_unavailable();
+    let _ = stream.set_write_timeout(Some(REJECT_WRITE_TIMEOUT));
+    if let Err(e) = stream.write_all(&response.to_bytes(false, true)) {
+        logging::warn!("failed to write busy response", error = e.to_string());
+    }
+}
+
+/// One request and its response, as the access log records them.
+pub(crate) struct Exchange {
+    pub request_id: String,
+    // None when no request could be read, e.g. it was malformed.
+    method: Option<Method>,
+    path: Option<String>,
+    // The pattern that matched, once routing found one.
+    pub route: Option<String>,
+    started: Instant,
+}
+
+impl Exchange {
+    pub fn start(request: Option<&Request>) -> Self {
+        // A caller's id is kept so that their logs and ours line up.
+        let request_id = request
+            .and_then(|request| request.headers.get("X-Request-Id"))
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic()))
+            .map(str::to_string)
+            .unwrap_or_else(new_request_id);
+
+        Exchange {
+            request_id,
+            method: request.map(|request| request.method),
+            path: request.map(|request| request.path.clone()),
+            route: None,
+            started: Instant::now(),
         }
     }
 
-    fn route_request(&self, request: &str) -> (String, String) {
-        match request {
-            r if r.starts_with("POST /users") => self.user_controller.create_user(r),
-            r if r.starts_with("GET /users/") => self.user_controller.get_user(r),
-            r if r.starts_with("GET /users") => self.user_controller.get_all_users(r),
-            r if r.starts_with("PUT /users/") => self.user_controller.update_user(r),
-            r if r.starts_with("DELETE /users/") => self.user_controller.delete_user(r),
-            _ => (NOT_FOUND.to_string(), "404 not found".to_string()),
+    /// Logs the access line, preceded by the error behind a 500 if the
+    /// response carries one, and records the request's metrics. `sent`
+    /// counts the body bytes written.
+    pub fn finish(self, response: &Response, sent: usize, client: Option<SocketAddr>) {
+        if let Some(cause) = &response.cause {
+            logging::error!(
+                "request failed",
+                request_id = self.request_id,
+                error = logging::error_chain(&**cause),
+            );
         }
+
+        let elapsed = self.started.elapsed();
+        let method = self.method.map_or("unknown", |method| method.as_str());
+        // Unmatched paths share one series so that scanners cannot add more.
+        metrics::record_request(method, self.route.as_deref().unwrap_or("unmatched"), response.status.as_u16(), elapsed);
+
+        let latency_ms = (elapsed.as_secs_f64() * 1_000_000.0).round() / 1000.0;
+        logging::info!(
+            "request",
+            request_id = self.request_id,
+            method = self.method.map(|method| method.as_str()),
+            path = self.path,
+            status = response.status.as_u16(),
+            bytes = sent,
+            latency_ms = latency_ms,
+            client = client,
+        );
     }
 }
+
+// Hashing a counter with per-process keys gives ids that are unique in
+// practice and cannot be guessed from one run to the next.
+fn new_request_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    static KEYS: OnceLock<RandomState> = OnceLock::new();
+
+    let mut hasher = KEYS.get_or_init(RandomState::new).build_hasher();
+    hasher.write_u64(NEXT.fetch_add(1, Ordering::Relaxed));
+    format!("{:016x}", hasher.finish())
+}
+
+pub(crate) fn method_not_allowed(allowed: &[Method]) -> Response {
+    let allow: Vec<&str> = allowed.iter().map(Method::as_str).collect();
+    Problem::new(StatusCode::MethodNotAllowed, "method_not_allowed")
+        .detail(format!("Allowed methods: {}", allow.join(", ")))
+        .into_response()
+        .with_header("Allow", allow.join(", "))
+}
+
+// The query string is carried over so `/users/?limit=5` keeps its parameters.
+pub(crate) fn redirect(path: &str, query: &Query) -> Response {
+    let location = match query.raw() {
+        "" => path.to_string(),
+        raw => format!("{}?{}", path, raw),
+    };
+    Response::new(StatusCode::PermanentRedirect).with_header("Location", location)
+}
+
+#[cfg(not(feature = "async"))]
+fn service_unavailable() -> Response {
+    Problem::new(StatusCode::ServiceUnavailable, "server_busy")
+        .detail("Server is busy, try again later")
+        .into_response()
+        .with_header("Retry-After", "1")
+}
+
+pub(crate) fn not_found(path: &str) -> Response {
+    Problem::new(StatusCode::NotFound, "route_not_found")
+        .detail(format!("No route for {}", path))
+        .into_response()
+}
+
+pub(crate) fn request_timeout() -> Response {
+    Problem::new(StatusCode::RequestTimeout, "request_timeout")
+        .detail("Request timed out")
+        .into_response()
+}
+
+#[cfg(not(feature = "async"))]
+fn loopback_addr(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), addr.port()),
+        IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), addr.port()),
+        _ => addr,
+    }
+}
+
+#[cfg(not(feature = "async"))]
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+pub(crate) fn parse_error_response(error: &ParseError) -> Response {
+    let (status, code) = match error {
+        ParseError::Malformed(_) => (StatusCode::BadRequest, "malformed_request"),
+        ParseError::UnsupportedMethod => (StatusCode::NotImplemented, "method_not_implemented"),
+        ParseError::UnsupportedVersion => (StatusCode::HttpVersionNotSupported, "version_not_supported"),
+        ParseError::HeadersTooLarge => (StatusCode::RequestHeaderFieldsTooLarge, "headers_too_large"),
+        ParseError::BodyTooLarge => (StatusCode::PayloadTooLarge, "payload_too_large"),
+    };
+    Problem::new(status, code).detail(error.to_string()).into_response()
+}
@@ -1,77 +1,340 @@
-use crate::models::User;
-use crate::repositories::UserRepository;
-use postgres::Error as PostgresError;
+use crate::models::user_patch::PatchError;
+use crate::models::{User, UserChanges, UserPage, UserPatch, UserQuery};
+use crate::repositories::AsyncUserRepository;
+use crate::database::{ConstraintViolation, DatabaseError, PoolState};
+use crate::validation::{FieldError, Validate};
 
 #[derive(Debug)]
 pub enum ServiceError {
-    ValidationError,
-    DatabaseError,
+    // Every field that failed, not just the first one.
+    ValidationError(Vec<FieldError>),
+    // The change clashes with existing data, e.g. an email that is taken.
+    Conflict(Vec<FieldError>),
+    // A JSON Patch operation could not be applied to the current user.
+    PatchFailed(String),
+    // The user is not at a version the client's `If-Match` accepts.
+    PreconditionFailed,
+    // A concurrent transaction won a race; the same request may succeed if retried.
+    Retryable,
+    DatabaseError(DatabaseError),
 }
 
-impl From<PostgresError> for ServiceError {
-    fn from(_error: PostgresError) -> Self {
-        ServiceError::DatabaseError
+impl From<DatabaseError> for ServiceError {
+    fn from(error: DatabaseError) -> Self {
+        match error {
+            DatabaseError::Constraint(ConstraintViolation::Unique { ref columns, .. }, _) => {
+                ServiceError::Conflict(
+                    columns
+                        .iter()
+                        .filter_map(|column| user_field(column))
+                        .map(|field| FieldError {
+                            field,
+                            code: "already_exists",
+                            message: format!("{} is already in use", field),
+                        })
+                        .collect(),
+                )
+            }
+            DatabaseError::Constraint(ConstraintViolation::ForeignKey { .. }, _) => ServiceError::Conflict(Vec::new()),
+            DatabaseError::Constraint(ConstraintViolation::NotNull { ref column }, _) => {
+                ServiceError::ValidationError(
+                    column
+                        .as_deref()
+                        .and_then(user_field)
+                        .map(|field| FieldError {
+                            field,
+                            code: "required",
+                            message: format!("{} must not be empty", field),
+                        })
+                        .into_iter()
+                        .collect(),
+                )
+            }
+            DatabaseError::Constraint(ConstraintViolation::Check { .. }, _) => ServiceError::ValidationError(Vec::new()),
+            DatabaseError::SerializationFailure(_) => ServiceError::Retryable,
+            error => ServiceError::DatabaseError(error),
+        }
+    }
+}
+
+// Maps a `users` column to the field name clients see.
+fn user_field(column: &str) -> Option<&'static str> {
+    match column {
+        "name" => Some("name"),
+        "email" => Some("email"),
+        _ => None,
+    }
+}
+
+fn validate_user(user: &User) -> Result<(), ServiceError> {
+    user.validate().map_err(ServiceError::ValidationError)
+}
+
+// How often PATCH re-reads and re-applies when another write lands between
+// its read and its update.
+const PATCH_ATTEMPTS: usize = 3;
+
+fn check_version(current: &User, versions: Option<&[i64]>) -> Result<(), ServiceError> {
+    match versions {
+        Some(versions) if !versions.contains(&current.version.unwrap_or_default()) => {
+            Err(ServiceError::PreconditionFailed)
+        }
+        _ => Ok(()),
     }
 }
 
+// Patches `current` and validates the merged result as a whole.
+fn patched_user(current: &User, patch: &UserPatch) -> Result<User, ServiceError> {
+    let patched = patch.apply(current).map_err(|e| match e {
+        PatchError::Invalid(errors) => ServiceError::ValidationError(errors),
+        PatchError::Failed(message) => ServiceError::PatchFailed(message),
+    })?;
+    validate_user(&patched)?;
+    Ok(patched)
+}
+
+/// Shared by the blocking and async servers; the blocking one drives these
+/// futures with `block_on`.
 pub struct UserService {
-    user_repository: UserRepository,
+    user_repository: Box<dyn AsyncUserRepository>,
 }
 
 impl UserService {
-    pub fn new(user_repository: UserRepository) -> Self {
+    pub fn new(user_repository: Box<dyn AsyncUserRepository>) -> Self {
         Self { user_repository }
     }
 
-    pub fn create_user(&mut self, user: &User) -> Result<(), ServiceError> {
-        // Business logic validation could go here
-        if user.name.trim().is_empty() {
-            return Err(ServiceError::ValidationError);
-        }
-        
-        if user.email.trim().is_empty() || !user.email.contains('@') {
-            return Err(ServiceError::ValidationError);
-        }
+    pub fn pool_state(&self) -> Option<PoolState> {
+        self.user_repository.pool_state()
+    }
 
-        self.user_repository.create(user).map_err(ServiceError::from)
+    pub async fn ping(&self) -> Result<(), DatabaseError> {
+        self.user_repository.ping().await
     }
 
-    pub fn get_user_by_id(&mut self, id: i32) -> Result<Option<User>, ServiceError> {
+    pub async fn pending_migrations(&self) -> Result<usize, DatabaseError> {
+        self.user_repository.pending_migrations().await
+    }
+
+    pub async fn create_user(&self, user: &User) -> Result<User, ServiceError> {
+        validate_user(user)?;
+
+        self.user_repository.create(user).await.map_err(ServiceError::from)
+    }
+
+    pub async fn get_user_by_id(&self, id: i32, include_deleted: bool) -> Result<Option<User>, ServiceError> {
         if id <= 0 {
             return Ok(None);
         }
         
-        self.user_repository.find_by_id(id).map_err(ServiceError::from)
+        self.user_repository.find_by_id(id, include_deleted).await.map_err(ServiceError::from)
     }
 
-    pub fn get_all_users(&mut self) -> Result<Vec<User>, ServiceError> {
-        self.user_repository.find_all().map_err(ServiceError::from)
+    pub async fn list_users(&self, query: &UserQuery) -> Result<UserPage, ServiceError> {
+        self.user_repository.find_page(query).await.map_err(ServiceError::from)
     }
 
-    pub fn update_user(&mut self, id: i32, user: &User) -> Result<bool, ServiceError> {
+    // `versions` are the ones the client's `If-Match` accepts, if it sent one.
+    pub async fn update_user(&self, id: i32, user: &User, versions: Option<&[i64]>) -> Result<Option<User>, ServiceError> {
         if id <= 0 {
-            return Ok(false);
+            return Ok(None);
         }
 
-        // Business logic validation
-        if user.name.trim().is_empty() {
-            return Err(ServiceError::ValidationError);
+        validate_user(user)?;
+
+        match self.user_repository.update(id, user, versions).await? {
+            Some(user) => Ok(Some(user)),
+            None => {
+                self.check_stale(id, versions).await?;
+                Ok(None)
+            }
         }
-        
-        if user.email.trim().is_empty() || !user.email.contains('@') {
-            return Err(ServiceError::ValidationError);
+    }
+
+    // Returns the patched user, or None if there is no user with this id.
+    pub async fn patch_user(&self, id: i32, patch: &UserPatch, versions: Option<&[i64]>) -> Result<Option<User>, ServiceError> {
+        if id <= 0 {
+            return Ok(None);
         }
 
-        let rows_affected = self.user_repository.update(id, user).map_err(ServiceError::from)?;
-        Ok(rows_affected > 0)
+        for _ in 0..PATCH_ATTEMPTS {
+            let current = match self.user_repository.find_by_id(id, false).await? {
+                Some(user) => user,
+                None => return Ok(None),
+            };
+            check_version(&current, versions)?;
+            let patched = patched_user(&current, patch)?;
+
+            let changes = UserChanges::between(&current, &patched);
+            if changes.is_empty() {
+                return Ok(Some(current));
+            }
+            let version = current.version.unwrap_or_default();
+            if let Some(user) = self.user_repository.update_fields(id, &changes, version).await? {
+                return Ok(Some(user));
+            }
+            // Changed or deleted since it was read; the next round sees which.
+        }
+        Err(ServiceError::Retryable)
     }
 
-    pub fn delete_user(&mut self, id: i32) -> Result<bool, ServiceError> {
+    pub async fn delete_user(&self, id: i32, versions: Option<&[i64]>) -> Result<bool, ServiceError> {
         if id <= 0 {
             return Ok(false);
         }
         
-        let rows_affected = self.user_repository.delete(id).map_err(ServiceError::from)?;
+        let rows_affected = self.user_repository.delete(id, versions).await.map_err(ServiceError::from)?;
+        if rows_affected == 0 {
+            self.check_stale(id, versions).await?;
+        }
         Ok(rows_affected > 0)
     }
+
+    // Undeletes a user; one that is not deleted is returned as it is. Fails
+    // with a conflict if its email was taken while it was deleted.
+    pub async fn restore_user(&self, id: i32) -> Result<Option<User>, ServiceError> {
+        if id <= 0 {
+            return Ok(None);
+        }
+
+        match self.user_repository.restore(id).await? {
+            Some(user) => Ok(Some(user)),
+            None => Ok(self.user_repository.find_by_id(id, false).await?),
+        }
+    }
+
+    pub async fn purge_deleted(&self, retention_days: i32) -> Result<u64, ServiceError> {
+        self.user_repository.purge_deleted(retention_days).await.map_err(ServiceError::from)
+    }
+
+    // A write guarded by `versions` touched no row: fails if that is because
+    // the user is at another version rather than gone.
+    async fn check_stale(&self, id: i32, versions: Option<&[i64]>) -> Result<(), ServiceError> {
+        if versions.is_some() && self.user_repository.find_by_id(id, false).await?.is_some() {
+            return Err(ServiceError::PreconditionFailed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::InMemoryUserRepository;
+    use crate::utils::block_on;
+    use serde_json::json;
+
+    fn service() -> UserService {
+        UserService::new(Box::new(InMemoryUserRepository::new()))
+    }
+
+    fn user(name: &str, email: &str) -> User {
+        serde_json::from_value(json!({ "id": null, "name": name, "email": email })).unwrap()
+    }
+
+    #[test]
+    fn create_with_a_taken_email_is_a_conflict() {
+        let service = service();
+        block_on(service.create_user(&user("Ada", "ada@example.com"))).unwrap();
+
+        match block_on(service.create_user(&user("Other Ada", "ada@example.com"))) {
+            Err(ServiceError::Conflict(errors)) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].field, "email");
+                assert_eq!(errors[0].code, "already_exists");
+            }
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn create_rejects_an_invalid_user() {
+        let result = block_on(service().create_user(&user("", "not an email")));
+        assert!(matches!(result, Err(ServiceError::ValidationError(errors)) if errors.len() == 2));
+    }
+
+    #[test]
+    fn a_stale_if_match_version_fails_the_precondition() {
+        let service = service();
+        let created = block_on(service.create_user(&user("Ada", "ada@example.com"))).unwrap();
+        let id = created.id.unwrap();
+        let changed = user("Ada L", "ada@example.com");
+
+        let updated = block_on(service.update_user(id, &changed, Some(&[1]))).unwrap().unwrap();
+        assert_eq!(updated.version, Some(2));
+
+        assert!(matches!(
+            block_on(service.update_user(id, &changed, Some(&[1]))),
+            Err(ServiceError::PreconditionFailed)
+        ));
+        let patch = UserPatch::Merge(json!({ "name": "Ada B" }));
+        assert!(matches!(block_on(service.patch_user(id, &patch, Some(&[1]))), Err(ServiceError::PreconditionFailed)));
+        assert!(matches!(block_on(service.delete_user(id, Some(&[1]))), Err(ServiceError::PreconditionFailed)));
+        // Unknown users are not found, whatever the If-Match says.
+        assert!(matches!(block_on(service.update_user(id + 1, &changed, Some(&[1]))), Ok(None)));
+    }
+
+    #[test]
+    fn a_patch_that_breaks_validation_is_rejected() {
+        let service = service();
+        let created = block_on(service.create_user(&user("Ada", "ada@example.com"))).unwrap();
+        let id = created.id.unwrap();
+
+        let patch = UserPatch::Merge(json!({ "name": "", "email": "nope" }));
+        match block_on(service.patch_user(id, &patch, None)) {
+            Err(ServiceError::ValidationError(errors)) => {
+                let fields: Vec<&str> = errors.iter().map(|error| error.field).collect();
+                assert_eq!(fields, ["name", "email"]);
+            }
+            other => panic!("expected validation errors, got {:?}", other),
+        }
+
+        // Nothing was written.
+        let stored = block_on(service.get_user_by_id(id, false)).unwrap().unwrap();
+        assert_eq!(stored.name, "Ada");
+        assert_eq!(stored.version, Some(1));
+    }
+
+    #[test]
+    fn deleted_users_can_be_restored_until_purged() {
+        let service = service();
+        let created = block_on(service.create_user(&user("Ada", "ada@example.com"))).unwrap();
+        let id = created.id.unwrap();
+
+        assert!(block_on(service.delete_user(id, None)).unwrap());
+        assert!(block_on(service.get_user_by_id(id, false)).unwrap().is_none());
+        assert!(block_on(service.get_user_by_id(id, true)).unwrap().is_some());
+        assert!(!block_on(service.delete_user(id, None)).unwrap());
+
+        let restored = block_on(service.restore_user(id)).unwrap().unwrap();
+        assert!(restored.deleted_at.is_none());
+        assert!(block_on(service.get_user_by_id(id, false)).unwrap().is_some());
+
+        assert!(block_on(service.delete_user(id, None)).unwrap());
+        // Still inside the retention period.
+        assert_eq!(block_on(service.purge_deleted(30)).unwrap(), 0);
+        assert_eq!(block_on(service.purge_deleted(0)).unwrap(), 1);
+        assert!(block_on(service.get_user_by_id(id, true)).unwrap().is_none());
+        assert!(block_on(service.restore_user(id)).unwrap().is_none());
+    }
+
+    #[test]
+    fn a_retention_period_past_the_calendar_purges_nothing() {
+        let service = service();
+        let created = block_on(service.create_user(&user("Ada", "ada@example.com"))).unwrap();
+        block_on(service.delete_user(created.id.unwrap(), None)).unwrap();
+
+        assert_eq!(block_on(service.purge_deleted(i32::MAX)).unwrap(), 0);
+    }
+
+    #[test]
+    fn restoring_fails_when_the_email_was_taken_meanwhile() {
+        let service = service();
+        let created = block_on(service.create_user(&user("Ada", "ada@example.com"))).unwrap();
+        let id = created.id.unwrap();
+        block_on(service.delete_user(id, None)).unwrap();
+        block_on(service.create_user(&user("New Ada", "ada@example.com"))).unwrap();
+
+        assert!(matches!(block_on(service.restore_user(id)), Err(ServiceError::Conflict(_))));
+    }
 }
@@ -0,0 +1,85 @@
+use crate::logging;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Exit status when a second signal arrives before draining finished.
+const FORCED_EXIT_CODE: i32 = 130;
+
+/// Flag shared between the accept loop and connection handlers that flips
+/// once SIGTERM or SIGINT has been received.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Runs `on_shutdown` on a background thread the first time SIGTERM or
+/// SIGINT arrives. A second signal exits the process immediately.
+#[cfg(not(feature = "async"))]
+pub fn on_signal<F>(on_shutdown: F) -> io::Result<()>
+where
+    F: Fn() + Send + 'static,
+{
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+    use signal_hook::low_level::signal_name;
+
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+
+    std::thread::Builder::new()
+        .name("signal-handler".to_string())
+        .spawn(move || {
+            let mut received = false;
+            for signal in signals.forever() {
+                if received {
+                    logging::warn!("received a second signal, exiting without draining", signal = signal_name(signal));
+                    std::process::exit(FORCED_EXIT_CODE);
+                }
+                received = true;
+                logging::info!("received signal, shutting down", signal = signal_name(signal));
+                on_shutdown();
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Async counterpart of `on_signal`: resolves on the first SIGTERM or SIGINT
+/// and arms a task that force-exits on a second one.
+#[cfg(feature = "async")]
+pub async fn signal_received() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+
+    tokio::select! {
+        _ = terminate.recv() => logging::info!("received signal, shutting down", signal = "SIGTERM"),
+        _ = interrupt.recv() => logging::info!("received signal, shutting down", signal = "SIGINT"),
+    }
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = interrupt.recv() => {}
+        }
+        logging::warn!("received a second signal, exiting without draining");
+        std::process::exit(FORCED_EXIT_CODE);
+    });
+
+    Ok(())
+}
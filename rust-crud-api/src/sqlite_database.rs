@@ -0,0 +1,126 @@
+use crate::config::PoolConfig;
+use crate::database::{ConstraintViolation, DatabaseError, PoolState};
+use crate::logging;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{ffi, Connection, ErrorCode, TransactionBehavior};
+
+pub type SqlitePooledClient = r2d2::PooledConnection<SqliteConnectionManager>;
+
+// SQLite counterpart of `migrations/`: entry N takes `PRAGMA user_version`
+// from N to N + 1. Append only, like the Postgres migrations.
+const SCHEMA: &[&str] = &[include_str!("../migrations/sqlite/0001_create_users.sql")];
+
+// Writers wait this long for the database lock before giving up with SQLITE_BUSY.
+const BUSY_TIMEOUT_MS: u32 = 5000;
+
+impl From<rusqlite::Error> for DatabaseError {
+    fn from(error: rusqlite::Error) -> Self {
+        let code = match error.sqlite_error() {
+            Some(code) => *code,
+            None => return DatabaseError::Sqlite(error),
+        };
+
+        let message = match &error {
+            rusqlite::Error::SqliteFailure(_, Some(message)) => message.as_str(),
+            _ => "",
+        };
+        let violation = match code.extended_code {
+            ffi::SQLITE_CONSTRAINT_UNIQUE | ffi::SQLITE_CONSTRAINT_PRIMARYKEY => ConstraintViolation::Unique {
+                constraint: None,
+                columns: failed_columns(message),
+            },
+            ffi::SQLITE_CONSTRAINT_FOREIGNKEY => ConstraintViolation::ForeignKey { constraint: None },
+            ffi::SQLITE_CONSTRAINT_CHECK => ConstraintViolation::Check { constraint: None },
+            ffi::SQLITE_CONSTRAINT_NOTNULL => ConstraintViolation::NotNull {
+                column: failed_columns(message).into_iter().next(),
+            },
+            _ if matches!(code.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => {
+                return DatabaseError::SerializationFailure(Box::new(error));
+            }
+            _ => return DatabaseError::Sqlite(error),
+        };
+
+        DatabaseError::Constraint(violation, Some(Box::new(error)))
+    }
+}
+
+// Pulls the column names out of a message like
+// `UNIQUE constraint failed: users.name, users.email`.
+fn failed_columns(message: &str) -> Vec<String> {
+    message
+        .split_once(": ")
+        .map(|(_, columns)| {
+            columns
+                .split(", ")
+                .map(|column| column.rsplit('.').next().unwrap_or(column).to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Pool of connections to one SQLite file, for running without Postgres.
+/// The schema is brought up to date when the pool is opened.
+#[derive(Clone)]
+pub struct SqliteDatabase {
+    pool: r2d2::Pool<SqliteConnectionManager>,
+}
+
+impl SqliteDatabase {
+    pub fn open(path: &str, pool_config: &PoolConfig) -> Result<Self, DatabaseError> {
+        let manager = SqliteConnectionManager::file(path).with_init(|connection| {
+            connection.execute_batch(&format!("PRAGMA busy_timeout = {}; PRAGMA foreign_keys = ON;", BUSY_TIMEOUT_MS))
+        });
+        let pool = r2d2::Pool::builder()
+            .min_idle(Some(pool_config.min_idle))
+            .max_size(pool_config.max_size)
+            .idle_timeout(pool_config.idle_timeout)
+            .connection_timeout(pool_config.connection_timeout)
+            .build(manager)?;
+
+        let mut client = pool.get()?;
+        // Lets readers carry on while a write is in progress.
+        client.execute_batch("PRAGMA journal_mode = WAL;")?;
+        setup_schema(&mut client)?;
+
+        Ok(SqliteDatabase { pool })
+    }
+
+    pub fn get_client(&self) -> Result<SqlitePooledClient, DatabaseError> {
+        Ok(self.pool.get()?)
+    }
+
+    // Steps of `SCHEMA` the file has not had applied, e.g. after another
+    // process replaced it.
+    pub fn pending_schema_steps(&self) -> Result<usize, DatabaseError> {
+        let version: u32 = self.get_client()?.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        Ok(SCHEMA.len().saturating_sub(version as usize))
+    }
+
+    pub fn state(&self) -> PoolState {
+        let state = self.pool.state();
+        PoolState {
+            connections: state.connections,
+            idle: state.idle_connections,
+            max_size: self.pool.max_size(),
+        }
+    }
+}
+
+// The write lock taken up front keeps two processes that open the same file
+// from applying a schema step twice.
+fn setup_schema(connection: &mut Connection) -> Result<(), DatabaseError> {
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version: u32 = transaction.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version as usize > SCHEMA.len() {
+        // An older build during a rolling deploy; the schema is ahead of it.
+        logging::warn!("SQLite schema is newer than this build knows about", version = version);
+    }
+
+    for (applied, sql) in SCHEMA.iter().enumerate().skip(version as usize) {
+        transaction.execute_batch(sql)?;
+        transaction.execute_batch(&format!("PRAGMA user_version = {}", applied + 1))?;
+    }
+
+    transaction.commit()?;
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use crate::logging;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Fixed-size pool of worker threads fed through a bounded queue.
+///
+/// Every queued item is passed to the same handler. `try_execute` never
+/// blocks: when all workers are busy and the queue is full the item is
+/// handed back so the caller can reject it.
+pub struct ThreadPool<T: Send + 'static> {
+    sender: Option<SyncSender<T>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> ThreadPool<T> {
+    pub fn new<F>(size: usize, queue_depth: usize, handler: F) -> Self
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        assert!(size > 0, "thread pool needs at least one worker");
+
+        let (sender, receiver) = mpsc::sync_channel(queue_depth);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+
+        let workers = (0..size)
+            .map(|id| {
+                let receiver = Arc::clone(&receiver);
+                let handler = Arc::clone(&handler);
+                thread::Builder::new()
+                    .name(format!("worker-{}", id))
+                    .spawn(move || worker_loop(receiver, handler))
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+
+        Self {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    pub fn try_execute(&self, item: T) -> Result<(), T> {
+        let sender = self.sender.as_ref().expect("sender is only taken on shutdown");
+        match sender.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(item)) | Err(TrySendError::Disconnected(item)) => Err(item),
+        }
+    }
+
+    /// Stops accepting work and waits up to `timeout` for the queue to drain
+    /// and every worker to exit. Returns the number of workers still busy
+    /// when the deadline passed; those threads are left detached.
+    pub fn shutdown(mut self, timeout: Duration) -> usize {
+        // Closing the channel lets workers finish the queue and then exit.
+        drop(self.sender.take());
+
+        // A timeout too long to add to the clock means waiting as long as it takes.
+        let deadline = Instant::now().checked_add(timeout);
+        while deadline.is_none_or(|deadline| Instant::now() < deadline)
+            && self.workers.iter().any(|worker| !worker.is_finished())
+        {
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+
+        let (finished, busy): (Vec<_>, Vec<_>) = self.workers.drain(..).partition(|worker| worker.is_finished());
+        for worker in finished {
+            if worker.join().is_err() {
+                logging::error!("worker thread exited with a panic");
+            }
+        }
+        busy.len()
+    }
+}
+
+impl<T: Send + 'static> Drop for ThreadPool<T> {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                logging::error!("worker thread exited with a panic");
+            }
+        }
+    }
+}
+
+fn worker_loop<T, F>(receiver: Arc<Mutex<Receiver<T>>>, handler: Arc<F>)
+where
+    F: Fn(T),
+{
+    loop {
+        let item = {
+            let receiver = match receiver.lock() {
+                Ok(receiver) => receiver,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            match receiver.recv() {
+                Ok(item) => item,
+                Err(_) => return,
+            }
+        };
+
+        // A panicking request must not take the worker down with it.
+        if panic::catch_unwind(AssertUnwindSafe(|| handler(item))).is_err() {
+            logging::error!("worker recovered from a panic while handling a connection");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+
+    // A pool whose handler reports each item as it starts on it, then waits
+    // for the test to release it.
+    fn gated_pool(size: usize, queue_depth: usize) -> (ThreadPool<u32>, Receiver<u32>, Sender<()>) {
+        let (started, started_rx) = mpsc::channel();
+        let (release, release_rx) = mpsc::channel::<()>();
+        let started = Mutex::new(started);
+        let release_rx = Mutex::new(release_rx);
+        let pool = ThreadPool::new(size, queue_depth, move |item| {
+            started.lock().unwrap().send(item).unwrap();
+            let _ = release_rx.lock().unwrap().recv();
+        });
+        (pool, started_rx, release)
+    }
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn a_full_queue_hands_the_item_back() {
+        let (pool, started, release) = gated_pool(1, 1);
+
+        assert_eq!(pool.try_execute(1), Ok(()));
+        assert_eq!(started.recv_timeout(WAIT), Ok(1));
+        // The worker is busy, so this waits in the queue and fills it.
+        assert_eq!(pool.try_execute(2), Ok(()));
+        assert_eq!(pool.try_execute(3), Err(3));
+
+        release.send(()).unwrap();
+        assert_eq!(started.recv_timeout(WAIT), Ok(2));
+        assert_eq!(pool.try_execute(4), Ok(()));
+
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        assert_eq!(pool.shutdown(WAIT), 0);
+        assert_eq!(started.recv_timeout(WAIT), Ok(4));
+    }
+
+    #[test]
+    fn a_panicking_item_does_not_stop_the_worker() {
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&handled);
+        let pool = ThreadPool::new(1, 4, move |item: u32| {
+            if item == 1 {
+                panic!("item {} failed", item);
+            }
+            seen.lock().unwrap().push(item);
+        });
+
+        for item in 0..4 {
+            assert_eq!(pool.try_execute(item), Ok(()));
+        }
+        assert_eq!(pool.shutdown(WAIT), 0);
+        assert_eq!(*handled.lock().unwrap(), [0, 2, 3]);
+    }
+
+    #[test]
+    fn shutdown_drains_the_queue() {
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&handled);
+        let pool = ThreadPool::new(2, 16, move |item: u32| {
+            thread::sleep(Duration::from_millis(5));
+            seen.lock().unwrap().push(item);
+        });
+
+        for item in 0..16 {
+            assert_eq!(pool.try_execute(item), Ok(()));
+        }
+        assert_eq!(pool.shutdown(WAIT), 0);
+
+        let mut handled = handled.lock().unwrap().clone();
+        handled.sort();
+        assert_eq!(handled, (0..16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shutdown_reports_workers_still_busy_at_the_deadline() {
+        let (pool, started, release) = gated_pool(2, 1);
+        assert_eq!(pool.try_execute(1), Ok(()));
+        assert_eq!(started.recv_timeout(WAIT), Ok(1));
+
+        assert_eq!(pool.shutdown(Duration::from_millis(100)), 1);
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn shutdown_without_a_reachable_deadline_waits_for_the_workers() {
+        let (pool, started, release) = gated_pool(1, 1);
+        assert_eq!(pool.try_execute(1), Ok(()));
+        assert_eq!(started.recv_timeout(WAIT), Ok(1));
+
+        release.send(()).unwrap();
+        assert_eq!(pool.shutdown(Duration::MAX), 0);
+    }
+}
@@ -1,21 +1,67 @@
-use crate::models::User;
-use serde_json;
-
-pub fn get_id_from_request(request: &str) -> &str {
-    request
-        .split("/")
-        .nth(2)
-        .unwrap_or_default()
-        .split_whitespace()
-        .next()
-        .unwrap_or_default()
+use crate::http::Request;
+use crate::models::{User, UserPatch};
+#[cfg(any(test, not(feature = "async")))]
+use std::future::Future;
+#[cfg(any(test, not(feature = "async")))]
+use std::pin::pin;
+#[cfg(any(test, not(feature = "async")))]
+use std::sync::Arc;
+#[cfg(any(test, not(feature = "async")))]
+use std::task::{Context, Poll, Wake, Waker};
+#[cfg(any(test, not(feature = "async")))]
+use std::thread::{self, Thread};
+
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+pub enum PatchBodyError {
+    UnsupportedMediaType,
+    Json(serde_json::Error),
+}
+
+pub fn get_user_from_request_body(request: &Request) -> Result<User, serde_json::Error> {
+    serde_json::from_slice(&request.body)
+}
+
+// Plain `application/json`, or no Content-Type at all, is read as a merge patch.
+pub fn get_patch_from_request_body(request: &Request) -> Result<UserPatch, PatchBodyError> {
+    let media_type = request
+        .headers
+        .get("Content-Type")
+        .map(|value| value.split(';').next().unwrap_or("").trim().to_ascii_lowercase());
+
+    match media_type.as_deref() {
+        None | Some(MERGE_PATCH) | Some("application/json") => serde_json::from_slice(&request.body)
+            .map(UserPatch::Merge)
+            .map_err(PatchBodyError::Json),
+        Some(JSON_PATCH) => serde_json::from_slice(&request.body)
+            .map(UserPatch::Json)
+            .map_err(PatchBodyError::Json),
+        Some(_) => Err(PatchBodyError::UnsupportedMediaType),
+    }
+}
+
+#[cfg(any(test, not(feature = "async")))]
+struct ThreadWaker(Thread);
+
+#[cfg(any(test, not(feature = "async")))]
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
 }
 
-pub fn get_user_from_request_body(request: &str) -> Result<User, serde_json::Error> {
-    let body = request
-        .split("\r\n\r\n")
-        .nth(1)
-        .unwrap_or_default();
-    
-    serde_json::from_str(body)
+// Runs a future to completion on the current thread, for the blocking server
+// and jobs. Futures over blocking repositories are ready on the first poll.
+#[cfg(any(test, not(feature = "async")))]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        thread::park();
+    }
 }
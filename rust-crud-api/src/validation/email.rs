@@ -0,0 +1,135 @@
+// Syntax check for an RFC 5322 `addr-spec` (`local-part@domain`). Comments
+// and folding whitespace are obsolete forms and are rejected, as are the
+// length limits from RFC 5321 that every mail server enforces anyway.
+
+const MAX_LOCAL_PART: usize = 64;
+const MAX_ADDRESS: usize = 254;
+
+pub fn is_valid_email(address: &str) -> bool {
+    if address.len() > MAX_ADDRESS {
+        return false;
+    }
+
+    // A quoted local part may itself contain '@', the domain never does.
+    let (local, domain) = match address.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+
+    local.len() <= MAX_LOCAL_PART
+        && (is_dot_atom(local) || is_quoted_string(local))
+        && (is_dot_atom(domain) || is_domain_literal(domain))
+}
+
+// atext from RFC 5322 section 3.2.3.
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c)
+}
+
+// One or more atoms separated by single dots, with no leading or trailing dot.
+fn is_dot_atom(text: &str) -> bool {
+    !text.is_empty() && text.split('.').all(|atom| !atom.is_empty() && atom.chars().all(is_atext))
+}
+
+fn is_quoted_string(text: &str) -> bool {
+    let inner = match text.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        Some(inner) => inner,
+        None => return false,
+    };
+
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // quoted-pair: a backslash followed by a visible character or space.
+            '\\' => match chars.next() {
+                Some(escaped) if escaped == ' ' || escaped.is_ascii_graphic() => {}
+                _ => return false,
+            },
+            '"' => return false,
+            c if c == ' ' || c.is_ascii_graphic() => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+// `[192.0.2.1]` or `[IPv6:...]`; dtext is any visible character except `[`, `]` and `\`.
+fn is_domain_literal(text: &str) -> bool {
+    match text.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+        Some(inner) => inner.chars().all(|c| c.is_ascii_graphic() && !"[]\\".contains(c)),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_addresses() {
+        let local_64 = format!("{}@example.com", "a".repeat(MAX_LOCAL_PART));
+        let total_254 = format!("a@{}.com", "b".repeat(MAX_ADDRESS - 6));
+        let valid = [
+            "user@example.com",
+            "first.last@example.co.uk",
+            "user+tag@example.com",
+            "!#$%&'*+-/=?^_`{|}~@example.com",
+            // A single-label domain is still an RFC 5322 dot-atom.
+            "user@localhost",
+            "\"quoted\"@example.com",
+            "\"with space\"@example.com",
+            "\"a@b\"@example.com",
+            "\"escaped \\\" quote\"@example.com",
+            "\"back\\\\slash\"@example.com",
+            "\"\"@example.com",
+            "user@[192.0.2.1]",
+            "user@[IPv6:2001:db8::1]",
+            &local_64,
+            &total_254,
+        ];
+
+        for address in valid {
+            assert!(is_valid_email(address), "{:?} should be valid", address);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        let local_65 = format!("{}@example.com", "a".repeat(MAX_LOCAL_PART + 1));
+        let total_255 = format!("a@{}.com", "b".repeat(MAX_ADDRESS - 5));
+        let invalid = [
+            "",
+            "plain",
+            "@example.com",
+            "user@",
+            "user@@example.com",
+            "us@er@example.com",
+            ".user@example.com",
+            "user.@example.com",
+            "us..er@example.com",
+            "us er@example.com",
+            "user@.example.com",
+            "user@example.com.",
+            "user@exa..mple.com",
+            "user@exa mple.com",
+            "user@exam(ple).com",
+            "user@[192.0.2.1",
+            "user@[a[b]",
+            "user@[a\\b]",
+            "\"unterminated@example.com",
+            "\"in\"side\"@example.com",
+            "\"trailing\\\"@example.com",
+            "\"tab\there\"@example.com",
+            "\"a\"b@example.com",
+            "(comment)user@example.com",
+            "usér@example.com",
+            "user@exämple.com",
+            &local_65,
+            &total_255,
+        ];
+
+        for address in invalid {
+            assert!(!is_valid_email(address), "{:?} should be invalid", address);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+pub mod email;
+
+use regex::Regex;
+use serde::Serialize;
+
+/// What was wrong with one field of a request body.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Implemented by models that can check their own fields. All failing fields
+/// are reported, so a client can fix a whole form in one round-trip.
+pub trait Validate {
+    fn validate(&self) -> Result<(), Vec<FieldError>>;
+}
+
+/// Collects field errors for a `Validate` implementation:
+///
+/// ```ignore
+/// let mut validator = Validator::new();
+/// validator.field("name", &self.name).required().length(1, 100);
+/// validator.finish()
+/// ```
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn field<'a, T: ?Sized>(&'a mut self, name: &'static str, value: &'a T) -> FieldRules<'a, T> {
+        FieldRules {
+            name,
+            value,
+            errors: &mut self.errors,
+            failed: false,
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Vec<FieldError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// Rules for a single field, applied in order. Once one fails the rest are
+/// skipped, so an empty email is reported as `required` and not also as
+/// `invalid_format`.
+pub struct FieldRules<'a, T: ?Sized> {
+    name: &'static str,
+    value: &'a T,
+    errors: &'a mut Vec<FieldError>,
+    failed: bool,
+}
+
+impl<T: ?Sized> FieldRules<'_, T> {
+    /// Applies an arbitrary check; `Err` carries the message for the client.
+    pub fn custom<F>(mut self, code: &'static str, rule: F) -> Self
+    where
+        F: FnOnce(&T) -> Result<(), String>,
+    {
+        if !self.failed {
+            if let Err(message) = rule(self.value) {
+                self.errors.push(FieldError {
+                    field: self.name,
+                    code,
+                    message,
+                });
+                self.failed = true;
+            }
+        }
+        self
+    }
+}
+
+impl<T: AsRef<str> + ?Sized> FieldRules<'_, T> {
+    pub fn required(self) -> Self {
+        let name = self.name;
+        self.custom("required", |value| {
+            if value.as_ref().trim().is_empty() {
+                Err(format!("{} must not be empty", name))
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    /// Bounds are inclusive and counted in characters, not bytes.
+    pub fn length(self, min: usize, max: usize) -> Self {
+        let name = self.name;
+        self.custom("length", |value| {
+            let length = value.as_ref().chars().count();
+            if length < min || length > max {
+                Err(format!("{} must be between {} and {} characters", name, min, max))
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    pub fn pattern(self, regex: &Regex, message: &str) -> Self {
+        let name = self.name;
+        self.custom("pattern", |value| {
+            if regex.is_match(value.as_ref()) {
+                Ok(())
+            } else {
+                Err(format!("{} {}", name, message))
+            }
+        })
+    }
+
+    pub fn email(self) -> Self {
+        let name = self.name;
+        self.custom("invalid_format", |value| {
+            if email::is_valid_email(value.as_ref()) {
+                Ok(())
+            } else {
+                Err(format!("{} must be a valid email address", name))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs `rules` on one field called `field` and returns the error codes.
+    fn codes(value: &str, rules: impl FnOnce(FieldRules<'_, str>) -> FieldRules<'_, str>) -> Vec<&'static str> {
+        let mut validator = Validator::new();
+        rules(validator.field("field", value));
+        match validator.finish() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.iter().map(|error| error.code).collect(),
+        }
+    }
+
+    #[test]
+    fn applies_each_rule() {
+        let digits = Regex::new("^[0-9]*$").unwrap();
+        let cases: &[(&str, &str, &[&str])] = &[
+            ("required", "x", &[]),
+            ("required", "", &["required"]),
+            ("required", " \t\n", &["required"]),
+            ("length", "ab", &["length"]),
+            ("length", "abc", &[]),
+            ("length", "abcde", &[]),
+            ("length", "abcdef", &["length"]),
+            // Characters, not bytes: five two-byte characters.
+            ("length", "ééééé", &[]),
+            ("length", "日本語日本語", &["length"]),
+            ("pattern", "123", &[]),
+            ("pattern", "12a", &["pattern"]),
+            ("email", "a@example.com", &[]),
+            ("email", "example.com", &["invalid_format"]),
+            ("custom", "even", &[]),
+            ("custom", "odd", &["parity"]),
+        ];
+
+        for (rule, value, expected) in cases {
+            let found = codes(value, |field| match *rule {
+                "required" => field.required(),
+                "length" => field.length(3, 5),
+                "pattern" => field.pattern(&digits, "must be digits"),
+                "email" => field.email(),
+                _ => field.custom("parity", |value| if value.len() % 2 == 0 { Ok(()) } else { Err("odd".to_string()) }),
+            });
+            assert_eq!(found, *expected, "{} on {:?}", rule, value);
+        }
+    }
+
+    #[test]
+    fn stops_at_the_first_failing_rule_of_a_field() {
+        assert_eq!(codes("", |field| field.required().length(1, 5).email()), ["required"]);
+        assert_eq!(codes("abcdefg", |field| field.required().length(1, 5).email()), ["length"]);
+        assert_eq!(codes("abc", |field| field.required().length(1, 5).email()), ["invalid_format"]);
+    }
+
+    #[test]
+    fn reports_every_failing_field() {
+        let mut validator = Validator::new();
+        validator.field("name", "").required();
+        validator.field("nickname", "ok").required();
+        validator.field("email", "nope").required().email();
+        let errors = validator.finish().unwrap_err();
+
+        let fields: Vec<(&str, &str)> = errors.iter().map(|error| (error.field, error.code)).collect();
+        assert_eq!(fields, [("name", "required"), ("email", "invalid_format")]);
+        assert_eq!(errors[0].message, "name must not be empty");
+        assert_eq!(errors[1].message, "email must be a valid email address");
+    }
+
+    #[test]
+    fn length_messages_name_the_bounds() {
+        let mut validator = Validator::new();
+        validator.field("name", "").length(1, 100);
+        assert_eq!(validator.finish().unwrap_err()[0].message, "name must be between 1 and 100 characters");
+    }
+}